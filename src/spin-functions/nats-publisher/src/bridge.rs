@@ -0,0 +1,215 @@
+// HTTP-to-NATS bridge client. Spin has no native NATS support, so messages
+// are published by POSTing to a bridge that holds the NATS connection.
+
+use anyhow::Result;
+use spin_sdk::http::{Method, Request, Response};
+
+/// What the bridge reported for a publish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PublishOutcome {
+    Published,
+    /// NATS reported no responders: nothing is subscribed to the subject.
+    /// Often expected (e.g. nobody is watching the conversation yet).
+    NoResponders,
+}
+
+pub trait Bridge {
+    fn publish(&self, subject: &str, data: &[u8]) -> Result<PublishOutcome>;
+}
+
+/// Bridge reached over Spin outbound HTTP at `POST {base_url}/publish/{subject}`.
+///
+/// The bridge signals no responders with a `503` whose body mentions
+/// "no responders", mirroring the NATS 503 status; any other non-2xx status
+/// is an error. Redirects (e.g. from a proxy in front of the bridge) are
+/// followed up to `max_redirects` hops, re-posting the same body, but only
+/// within the bridge's own origin.
+pub struct HttpBridge {
+    base_url: String,
+    max_redirects: usize,
+}
+
+impl HttpBridge {
+    pub fn new(base_url: &str) -> Self {
+        HttpBridge { base_url: base_url.trim_end_matches('/').to_string(), max_redirects: 0 }
+    }
+
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+}
+
+impl Bridge for HttpBridge {
+    fn publish(&self, subject: &str, data: &[u8]) -> Result<PublishOutcome> {
+        let url = format!("{}/publish/{}", self.base_url, subject);
+        let response = follow_redirects(&url, self.max_redirects, |url| {
+            let request = Request::builder()
+                .method(Method::Post)
+                .uri(url)
+                .body(data.to_vec())
+                .build();
+            let response: Response = spin_sdk::http::run(spin_sdk::http::send(request))?;
+            Ok(BridgeResponse {
+                status: *response.status(),
+                location: response.header("location").and_then(|v| v.as_str()).map(String::from),
+                body: response.into_body(),
+            })
+        })?;
+        outcome(response.status, &response.body)
+    }
+}
+
+/// The parts of a bridge response the publisher looks at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BridgeResponse {
+    pub status: u16,
+    pub location: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Send to `url` with `send`, following up to `max_redirects` redirects
+/// that stay on `url`'s origin, so a redirect can't send the payload to
+/// another host. Running out of hops, a redirect elsewhere, or one without
+/// a `Location`, is reported as a redirect rather than as a generic bridge
+/// failure.
+pub fn follow_redirects(
+    url: &str,
+    max_redirects: usize,
+    mut send: impl FnMut(&str) -> Result<BridgeResponse>,
+) -> Result<BridgeResponse> {
+    let allowed_origin = origin(url);
+    let mut url = url.to_string();
+    for hop in 0..=max_redirects {
+        let response = send(&url)?;
+        if !(300..=399).contains(&response.status) {
+            return Ok(response);
+        }
+        let Some(location) = &response.location else {
+            anyhow::bail!("bridge returned redirect {} without a Location header", response.status);
+        };
+        let next = resolve(&url, location);
+        if !origin(&next).eq_ignore_ascii_case(allowed_origin) {
+            anyhow::bail!("bridge redirected to {}, outside {}; only same-origin redirects are followed", next, allowed_origin);
+        }
+        if hop == max_redirects {
+            break;
+        }
+        url = next;
+    }
+    anyhow::bail!(
+        "bridge redirected {} time(s) from {}; raise bridge_max_redirects to follow",
+        max_redirects + 1,
+        url
+    )
+}
+
+/// Scheme, host and port of `url`, e.g. `http://bridge:8080`.
+fn origin(url: &str) -> &str {
+    let origin_end = url
+        .find("://")
+        .and_then(|scheme_end| url[scheme_end + 3..].find('/').map(|i| scheme_end + 3 + i))
+        .unwrap_or(url.len());
+    &url[..origin_end]
+}
+
+/// Resolve a `Location` against the URL that returned it. Absolute URLs are
+/// used as they are; paths are taken relative to the same origin.
+fn resolve(base: &str, location: &str) -> String {
+    if location.contains("://") {
+        return location.to_string();
+    }
+    let origin_end = origin(base).len();
+    if location.starts_with('/') {
+        format!("{}{}", &base[..origin_end], location)
+    } else {
+        let dir_end = base.rfind('/').filter(|i| *i >= origin_end).unwrap_or(origin_end);
+        format!("{}/{}", &base[..dir_end], location)
+    }
+}
+
+/// Interpret a bridge response.
+pub fn outcome(status: u16, body: &[u8]) -> Result<PublishOutcome> {
+    match status {
+        200..=299 => Ok(PublishOutcome::Published),
+        503 if is_no_responders(body) => Ok(PublishOutcome::NoResponders),
+        _ => anyhow::bail!("bridge returned {}: {}", status, String::from_utf8_lossy(body)),
+    }
+}
+
+fn is_no_responders(body: &[u8]) -> bool {
+    String::from_utf8_lossy(body).to_ascii_lowercase().contains("no responders")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome() {
+        assert_eq!(outcome(202, b"").unwrap(), PublishOutcome::Published);
+        assert_eq!(
+            outcome(503, br#"{"error": "nats: no responders available for request"}"#).unwrap(),
+            PublishOutcome::NoResponders
+        );
+        // A 503 for any other reason is still a failure
+        assert!(outcome(503, b"nats: connection closed").is_err());
+        assert!(outcome(500, b"").is_err());
+    }
+
+    fn redirect(location: &str) -> BridgeResponse {
+        BridgeResponse { status: 307, location: Some(location.to_string()), body: Vec::new() }
+    }
+
+    #[test]
+    fn test_single_redirect_followed() {
+        let mut sent = Vec::new();
+        let response = follow_redirects("http://bridge:8080/publish/chat.a.tokens", 3, |url| {
+            sent.push(url.to_string());
+            Ok(match sent.len() {
+                1 => redirect("/v2/publish/chat.a.tokens"),
+                _ => BridgeResponse { status: 202, location: None, body: Vec::new() },
+            })
+        })
+        .unwrap();
+        assert_eq!(response.status, 202);
+        assert_eq!(sent, ["http://bridge:8080/publish/chat.a.tokens", "http://bridge:8080/v2/publish/chat.a.tokens"]);
+    }
+
+    #[test]
+    fn test_redirect_loop_bounded() {
+        let mut hops = 0;
+        let err = follow_redirects("http://bridge:8080/publish/a", 2, |_| {
+            hops += 1;
+            Ok(redirect("http://bridge:8080/publish/a"))
+        })
+        .unwrap_err();
+        assert_eq!(hops, 3);
+        assert!(err.to_string().contains("redirected 3 time(s)"), "{}", err);
+
+        // Not following at all still names the redirect
+        let err = follow_redirects("http://bridge:8080/publish/a", 0, |_| Ok(redirect("/elsewhere"))).unwrap_err();
+        assert!(err.to_string().contains("redirected 1 time(s)"), "{}", err);
+    }
+
+    #[test]
+    fn test_cross_origin_redirect_refused() {
+        for location in ["http://attacker.example/publish/a", "https://bridge:8080/publish/a", "http://bridge:9090/publish/a"] {
+            let mut sent = Vec::new();
+            let err = follow_redirects("http://bridge:8080/publish/a", 3, |url| {
+                sent.push(url.to_string());
+                Ok(redirect(location))
+            })
+            .unwrap_err();
+            assert_eq!(sent, ["http://bridge:8080/publish/a"], "{}", location);
+            assert!(err.to_string().contains("only same-origin redirects"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_resolve_location() {
+        assert_eq!(resolve("http://a:1/publish/x", "http://b/y"), "http://b/y");
+        assert_eq!(resolve("http://a:1/publish/x", "/y"), "http://a:1/y");
+        assert_eq!(resolve("http://a:1/publish/x", "y"), "http://a:1/publish/y");
+    }
+}
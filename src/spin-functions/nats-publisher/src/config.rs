@@ -0,0 +1,143 @@
+// Runtime configuration sourced from Spin application variables.
+// Every variable read here must also be declared in spin.toml.
+
+use anyhow::Result;
+use spin_common::variables::{parse, Variables};
+
+/// Bridge used when `bridge_url` is unset.
+pub const DEFAULT_BRIDGE_URL: &str = "http://nats-http-bridge:8080";
+
+/// How long a used nonce is remembered when `nonce_ttl_secs` is unset.
+pub const DEFAULT_NONCE_TTL_SECS: u64 = 300;
+
+/// How a publish with no subscribers is reported to the caller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NoRespondersResponse {
+    /// `200` with `{"status": "no_subscribers"}`.
+    #[default]
+    Body,
+    /// An empty `204`.
+    NoContent,
+}
+
+impl std::str::FromStr for NoRespondersResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "body" => Ok(NoRespondersResponse::Body),
+            "204" => Ok(NoRespondersResponse::NoContent),
+            other => anyhow::bail!("unknown no_responders_response '{}', expected 'body' or '204'", other),
+        }
+    }
+}
+
+/// Settings resolved once per request.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub bridge_url: String,
+    pub no_responders_response: NoRespondersResponse,
+    /// Same-origin redirects from the bridge (e.g. a proxy in front of it)
+    /// followed before giving up; zero reports the first one as an error.
+    pub bridge_max_redirects: usize,
+    /// Publish empty or whitespace-only bodies (e.g. keep-alives) instead of
+    /// rejecting them.
+    pub allow_empty_publish: bool,
+    /// Inspect content with the configured policy before publishing,
+    /// dropping or redacting it at ingress. Payloads that aren't UTF-8 are
+    /// refused.
+    pub inspect_on_publish: bool,
+    /// Inspection settings, read from the same variables as the
+    /// subscriber's, so ingress applies the same policies.
+    pub inspection: nats_subscriber::config::Settings,
+    /// OTLP/HTTP collector base URL for trace export; tracing is off when
+    /// unset.
+    pub otlp_endpoint: Option<String>,
+    /// Require a unique `X-Nonce` on each publish, rejecting replays. The
+    /// nonce is only used up once the payload has passed its checks.
+    pub require_nonce: bool,
+    /// How long a used nonce is remembered, at least a second; a replay
+    /// after that is accepted.
+    pub nonce_ttl_secs: u64,
+    /// Shared secret verdicts posted to `/reply` must be signed with (see
+    /// `nats_subscriber::signature`); replies are refused while it is unset.
+    pub reply_key: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bridge_url: DEFAULT_BRIDGE_URL.to_string(),
+            no_responders_response: NoRespondersResponse::Body,
+            bridge_max_redirects: 0,
+            allow_empty_publish: false,
+            inspect_on_publish: false,
+            inspection: nats_subscriber::config::Settings::default(),
+            otlp_endpoint: None,
+            require_nonce: false,
+            nonce_ttl_secs: DEFAULT_NONCE_TTL_SECS,
+            reply_key: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(vars: &dyn Variables) -> Result<Self> {
+        let mut settings = Settings::default();
+
+        if let Some(url) = vars.get("bridge_url") {
+            settings.bridge_url = url;
+        }
+        if let Some(response) = parse(vars, "no_responders_response")? {
+            settings.no_responders_response = response;
+        }
+        if let Some(value) = parse(vars, "bridge_max_redirects")? {
+            settings.bridge_max_redirects = value;
+        }
+        if let Some(allow) = parse(vars, "allow_empty_publish")? {
+            settings.allow_empty_publish = allow;
+        }
+        if let Some(inspect) = parse(vars, "inspect_on_publish")? {
+            settings.inspect_on_publish = inspect;
+        }
+        settings.inspection = nats_subscriber::config::Settings::load(vars)?;
+        settings.otlp_endpoint = vars.get("otlp_endpoint");
+        if let Some(require) = parse(vars, "require_nonce")? {
+            settings.require_nonce = require;
+        }
+        if let Some(ttl) = parse(vars, "nonce_ttl_secs")? {
+            if ttl == 0 {
+                anyhow::bail!("invalid `nonce_ttl_secs` variable: must be at least 1");
+            }
+            settings.nonce_ttl_secs = ttl;
+        }
+        settings.reply_key = vars.get("reply_key");
+
+        Ok(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_load() {
+        let settings = Settings::load(&HashMap::new()).unwrap();
+        assert_eq!(settings.bridge_url, DEFAULT_BRIDGE_URL);
+        assert_eq!(settings.no_responders_response, NoRespondersResponse::Body);
+
+        let vars = HashMap::from([("no_responders_response", "204")]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.no_responders_response, NoRespondersResponse::NoContent);
+        assert!(Settings::load(&HashMap::from([("no_responders_response", "500")])).is_err());
+    }
+
+    #[test]
+    fn test_zero_nonce_ttl_rejected() {
+        let settings = Settings::load(&HashMap::from([("nonce_ttl_secs", "60")])).unwrap();
+        assert_eq!(settings.nonce_ttl_secs, 60);
+        assert!(Settings::load(&HashMap::from([("nonce_ttl_secs", "0")])).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+// Replay protection for publishes. Each publish carries a fresh
+// `X-Nonce`; nonces are remembered in KV until their TTL passes, so a
+// captured request replayed within that window is rejected.
+//
+// KV can't list keys, so each claimed nonce is also indexed under the
+// `ttl_secs` window it was claimed in. Once a window is two behind the
+// current one all its nonces have expired, and later claims delete them a
+// few at a time, keeping KV at about two windows' worth of nonces.
+
+use anyhow::Result;
+use nats_subscriber::kv::Store;
+
+pub const NONCE_HEADER: &str = "x-nonce";
+
+/// Longest nonce accepted, so clients can't mint arbitrarily long keys.
+pub const MAX_NONCE_LEN: usize = 128;
+
+/// Most KV entries a single claim sweeps.
+const SWEEP_BATCH: usize = 16;
+
+/// Oldest window that may still have nonces to sweep.
+const OLDEST_WINDOW_KEY: &str = "nonce-window/oldest";
+
+fn key(nonce: &str) -> String {
+    format!("nonce/{}", nonce)
+}
+
+fn window_key(window: u64) -> String {
+    format!("nonce-window/{}", window)
+}
+
+fn read_u64(store: &dyn Store, key: &str) -> Result<Option<u64>> {
+    match store.get(key)? {
+        Some(raw) => Ok(Some(std::str::from_utf8(&raw)?.trim().parse()?)),
+        None => Ok(None),
+    }
+}
+
+fn read_index(store: &dyn Store, key: &str) -> Result<Vec<String>> {
+    match store.get(key)? {
+        Some(raw) => Ok(serde_json::from_slice(&raw)?),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Record `nonce` as used until `ttl_secs` after `now_secs`, returning
+/// `false` if it was already used and hasn't expired.
+///
+/// This is a read-then-write, so two concurrent requests with the same
+/// nonce can both get through; sequential replays, the usual case, can't.
+/// A concurrent claim can also drop another's index entry, leaving that
+/// nonce's key behind, but never lets a replay through.
+pub fn claim(store: &dyn Store, nonce: &str, now_secs: u64, ttl_secs: u64) -> Result<bool> {
+    let key = key(nonce);
+    if read_u64(store, &key)?.is_some_and(|expires| now_secs < expires) {
+        return Ok(false);
+    }
+    store.set(&key, (now_secs + ttl_secs).to_string().as_bytes())?;
+
+    let window = now_secs / ttl_secs.max(1);
+    let index_key = window_key(window);
+    let mut index = read_index(store, &index_key)?;
+    index.push(nonce.to_string());
+    store.set(&index_key, &serde_json::to_vec(&index)?)?;
+    sweep(store, window, now_secs)?;
+    Ok(true)
+}
+
+/// Delete up to `SWEEP_BATCH` entries from windows two or more behind
+/// `window`. A nonce claimed again since keeps its newer expiry.
+fn sweep(store: &dyn Store, window: u64, now_secs: u64) -> Result<()> {
+    let mut oldest = read_u64(store, OLDEST_WINDOW_KEY)?.unwrap_or(window);
+    let mut budget = SWEEP_BATCH;
+    while oldest + 1 < window && budget > 0 {
+        let index_key = window_key(oldest);
+        let mut index = read_index(store, &index_key)?;
+        while budget > 0 {
+            let Some(nonce) = index.pop() else {
+                break;
+            };
+            let key = key(&nonce);
+            if read_u64(store, &key)?.is_some_and(|expires| expires <= now_secs) {
+                store.delete(&key)?;
+            }
+            budget -= 1;
+        }
+        if !index.is_empty() {
+            store.set(&index_key, &serde_json::to_vec(&index)?)?;
+            break;
+        }
+        store.delete(&index_key)?;
+        oldest += 1;
+        budget = budget.saturating_sub(1);
+    }
+    store.set(OLDEST_WINDOW_KEY, oldest.to_string().as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    /// In-memory KV store.
+    #[derive(Default)]
+    struct MockStore(RefCell<HashMap<String, Vec<u8>>>);
+
+    impl Store for MockStore {
+        fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+            self.0.borrow_mut().insert(key.to_string(), value.to_vec());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> Result<()> {
+            self.0.borrow_mut().remove(key);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_expired_nonces_swept() {
+        let store = MockStore::default();
+        let ttl_secs = 60;
+        for i in 0..40 {
+            assert!(claim(&store, &format!("n-{}", i), i, ttl_secs).unwrap());
+        }
+        assert!(!claim(&store, "n-0", 59, ttl_secs).unwrap());
+        // Claimed again once expired: the sweep must not forget it
+        assert!(claim(&store, "n-1", 125, ttl_secs).unwrap());
+
+        // Claims two windows on sweep the first window away
+        for i in 0..10 {
+            assert!(claim(&store, &format!("later-{}", i), 180 + i, ttl_secs).unwrap());
+        }
+        let keys = store.0.borrow();
+        assert!(!keys.contains_key(&key("n-0")));
+        assert!(!keys.contains_key(&key("n-39")));
+        assert!(!keys.contains_key(&window_key(0)));
+        assert!(keys.contains_key(&key("n-1")));
+        drop(keys);
+        assert!(!claim(&store, "n-1", 184, ttl_secs).unwrap());
+    }
+}
@@ -0,0 +1,67 @@
+// Backpressure. An overloaded subscriber tells the bridge to slow down
+// with a `503` and a `Retry-After` instead of dropping messages silently.
+// Load is measured two ways, each optional: requests in flight across all
+// instances (`backpressure_max_inflight`), and how long the KV write that
+// counts them takes (`backpressure_max_kv_latency_ms`), since a slow store
+// stalls every stateful check behind it.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use spin_common::problem::Problem;
+use spin_sdk::http::Response;
+
+use crate::clock::Clock;
+use crate::concurrency::{self, InflightGuard};
+use crate::config::Settings;
+use crate::kv::{self, Store};
+
+pub const REASON_CODE: &str = "OVERLOADED";
+
+pub const RETRY_AFTER_HEADER: &str = "retry-after";
+
+/// Whether a request may proceed.
+pub enum Admission<'a> {
+    /// Holding a slot of the in-flight limit, if there is one, until the
+    /// guard drops.
+    Admitted(Option<InflightGuard<'a>>),
+    /// Over a threshold, described for the response.
+    Overloaded(String),
+}
+
+/// Measure load against the configured thresholds.
+pub fn admit<'a>(store: &'a dyn Store, clock: &dyn Clock, settings: &Settings) -> Result<Admission<'a>> {
+    let (max_inflight, max_latency_ms) = (settings.backpressure_max_inflight, settings.backpressure_max_kv_latency_ms);
+    if max_inflight.is_none() && max_latency_ms.is_none() {
+        return Ok(Admission::Admitted(None));
+    }
+    let started = clock.now();
+    let guard = match max_inflight {
+        Some(limit) => match concurrency::acquire_total(store, limit)? {
+            Some(guard) => Some(guard),
+            None => return Ok(Admission::Overloaded(format!("{} requests in flight", limit))),
+        },
+        None => {
+            kv::read_counter(store, concurrency::TOTAL_KEY)?;
+            None
+        }
+    };
+    let latency = clock.now().saturating_sub(started);
+    if let Some(max_ms) = max_latency_ms {
+        if latency > Duration::from_millis(max_ms) {
+            // Dropping the guard gives back the slot just taken
+            return Ok(Admission::Overloaded(format!("KV round trip took {}ms", latency.as_millis())));
+        }
+    }
+    Ok(Admission::Admitted(guard))
+}
+
+/// The `503` asking the bridge to pause delivery for `retry_after_secs`.
+pub fn overloaded(detail: &str, retry_after_secs: u64) -> Response {
+    let mut response = Problem::new(503)
+        .with_detail(format!("subscriber overloaded: {}", detail))
+        .with_extension("reason_code", REASON_CODE)
+        .into_response();
+    response.set_header(RETRY_AFTER_HEADER, retry_after_secs.to_string());
+    response
+}
@@ -0,0 +1,131 @@
+// Canary tokens. Defenders plant unique fake secrets where only an attacker
+// would find them, so one showing up in model output means a breach. With
+// `canary_tokens` set, the values stored as a JSON array of strings under the
+// `canary_tokens` KV key are loaded with the policy, and the `canary`
+// detector drops content carrying one. A drop also publishes a high-priority
+// alert to `alerts` when `alert_bridge_url` is set.
+//
+// Token values never leave the component. Reasons and alerts name the
+// matched token by its SHA-256, which whoever planted it can compute to
+// tell which one fired.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::config::KvFailureMode;
+use crate::kv::Store;
+use crate::outbound::Outbound;
+use crate::NatsMessage;
+
+pub const KEY: &str = "canary_tokens";
+pub const SUBJECT: &str = "alerts";
+pub const REASON_CODE: &str = "CANARY_TOKEN";
+
+/// Payload published when a canary fires.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    pub kind: String,
+    pub priority: String,
+    /// Subject the message carrying the canary arrived on.
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    /// Hex SHA-256 of the matched token.
+    pub token_sha256: String,
+}
+
+/// The canary tokens a policy was loaded with. Never serialized, so tokens
+/// can't leak through a sealed or echoed policy, or be set by a reload.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Tokens {
+    /// Canary checks are off.
+    #[default]
+    Off,
+    Loaded(Vec<String>),
+    /// KV was unreachable under `fail_closed`, so everything is dropped.
+    Unavailable,
+}
+
+impl Tokens {
+    /// Load the stored tokens. An unreadable entry is handled per
+    /// `kv_failure_mode`: checks are skipped failing open, and every message
+    /// is dropped failing closed.
+    pub fn load(store: &dyn Store, mode: KvFailureMode) -> Self {
+        match stored(store) {
+            Ok(tokens) => Tokens::Loaded(tokens),
+            Err(e) if mode == KvFailureMode::FailClosed => {
+                eprintln!("warning: canary tokens unavailable, failing closed: {:#}", e);
+                Tokens::Unavailable
+            }
+            Err(e) => {
+                eprintln!("warning: canary tokens unavailable, failing open: {:#}", e);
+                Tokens::Off
+            }
+        }
+    }
+
+    /// The SHA-256 of the first token in `content`, if any.
+    pub fn find(&self, content: &str) -> Option<String> {
+        let Tokens::Loaded(tokens) = self else {
+            return None;
+        };
+        tokens
+            .iter()
+            .find(|token| !token.is_empty() && content.contains(token.as_str()))
+            .map(|token| digest(token))
+    }
+}
+
+fn stored(store: &dyn Store) -> Result<Vec<String>> {
+    let Some(raw) = store.get(KEY)? else {
+        return Ok(Vec::new());
+    };
+    serde_json::from_slice(&raw).context("stored canary tokens are invalid")
+}
+
+fn digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Publish a high-priority alert that `message` carried a canary.
+pub(crate) fn alert(outbound: &dyn Outbound, bridge_url: &str, message: &NatsMessage, token_sha256: &str) -> Result<()> {
+    let url = format!("{}/publish/{}", bridge_url.trim_end_matches('/'), SUBJECT);
+    let alert = Alert {
+        kind: "canary_token".into(),
+        priority: "high".into(),
+        subject: message.subject.clone(),
+        sequence: message.sequence,
+        token_sha256: token_sha256.to_string(),
+    };
+    let (status, _) = outbound.post(&url, "application/json", serde_json::to_vec(&alert)?)?;
+    anyhow::ensure!((200..300).contains(&status), "bridge returned {}", status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_stored_tokens_matched() {
+        let store = MemoryStore::default();
+        let tokens = Tokens::load(&store, KvFailureMode::FailOpen);
+        assert_eq!(tokens, Tokens::Loaded(Vec::new()));
+        assert_eq!(tokens.find("AKIACANARY123"), None);
+
+        store.set(KEY, br#"["", "AKIACANARY123", "canary-db-pass"]"#).unwrap();
+        let tokens = Tokens::load(&store, KvFailureMode::FailOpen);
+        assert_eq!(tokens.find("key is AKIACANARY123"), Some(digest("AKIACANARY123")));
+        assert_eq!(tokens.find("nothing planted here"), None);
+    }
+
+    #[test]
+    fn test_unreadable_tokens_follow_kv_failure_mode() {
+        let store = MemoryStore::default();
+        store.set(KEY, b"not json").unwrap();
+        assert_eq!(Tokens::load(&store, KvFailureMode::FailOpen), Tokens::Off);
+        assert_eq!(Tokens::load(&store, KvFailureMode::FailClosed), Tokens::Unavailable);
+    }
+}
@@ -0,0 +1,49 @@
+// Wall-clock time, behind a trait so tests can control it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock {
+    /// Time since the Unix epoch.
+    fn now(&self) -> Duration;
+
+    fn now_ms(&self) -> u64 {
+        self.now().as_millis() as u64
+    }
+}
+
+/// The host's system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+    }
+}
+
+/// Clock that starts at the epoch (or `start`) and advances by a fixed step
+/// on every read, so elapsed times in tests are exact.
+#[cfg(test)]
+pub struct StepClock {
+    step: Duration,
+    now: std::cell::Cell<Duration>,
+}
+
+#[cfg(test)]
+impl StepClock {
+    pub fn new(step: Duration) -> Self {
+        StepClock::starting_at(Duration::ZERO, step)
+    }
+
+    pub fn starting_at(start: Duration, step: Duration) -> Self {
+        StepClock { step, now: std::cell::Cell::new(start) }
+    }
+}
+
+#[cfg(test)]
+impl Clock for StepClock {
+    fn now(&self) -> Duration {
+        let now = self.now.get();
+        self.now.set(now + self.step);
+        now
+    }
+}
@@ -0,0 +1,92 @@
+// Per-conversation in-flight request cap, so one busy conversation can't
+// monopolize an instance, and the total across conversations that
+// backpressure watches. Counts live in KV because each request runs in a
+// fresh component instance.
+//
+// Spin KV has no atomic increment, so the count is best-effort under races;
+// it bounds a runaway conversation rather than enforcing an exact limit.
+
+use anyhow::Result;
+
+use crate::kv::{self, Store};
+
+/// Count of requests in flight across every conversation.
+pub const TOTAL_KEY: &str = "inflight_total";
+
+fn key(conversation_id: &str) -> String {
+    format!("inflight/{}", conversation_id)
+}
+
+/// Holds one in-flight slot for a conversation and releases it on drop, so
+/// the count is decremented on every exit path including errors.
+pub struct InflightGuard<'a> {
+    store: &'a dyn Store,
+    key: String,
+}
+
+impl Drop for InflightGuard<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = kv::add_counter(self.store, &self.key, -1) {
+            eprintln!("warning: failed to release in-flight slot {}: {}", self.key, e);
+        }
+    }
+}
+
+/// Claim an in-flight slot, returning `None` when the conversation already
+/// has `limit` requests in flight.
+pub fn acquire<'a>(store: &'a dyn Store, conversation_id: &str, limit: u64) -> Result<Option<InflightGuard<'a>>> {
+    acquire_slot(store, key(conversation_id), limit)
+}
+
+/// Claim one of `limit` slots shared by every request.
+pub fn acquire_total(store: &dyn Store, limit: u64) -> Result<Option<InflightGuard<'_>>> {
+    acquire_slot(store, TOTAL_KEY.to_string(), limit)
+}
+
+fn acquire_slot(store: &dyn Store, key: String, limit: u64) -> Result<Option<InflightGuard<'_>>> {
+    let count = kv::add_counter(store, &key, 1)?;
+    let guard = InflightGuard { store, key };
+    if count > limit {
+        // Dropping the guard gives back the slot just taken
+        return Ok(None);
+    }
+    Ok(Some(guard))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    fn inflight(store: &MemoryStore) -> u64 {
+        kv::read_counter(store, &key("abc")).unwrap()
+    }
+
+    #[test]
+    fn test_acquire_up_to_limit() {
+        let store = MemoryStore::default();
+        let first = acquire(&store, "abc", 2).unwrap();
+        let second = acquire(&store, "abc", 2).unwrap();
+        assert!(first.is_some() && second.is_some());
+        assert!(acquire(&store, "abc", 2).unwrap().is_none());
+        assert_eq!(inflight(&store), 2);
+
+        // Other conversations are unaffected
+        assert!(acquire(&store, "other", 2).unwrap().is_some());
+
+        drop(first);
+        assert_eq!(inflight(&store), 1);
+        assert!(acquire(&store, "abc", 2).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_released_on_error_path() {
+        let store = MemoryStore::default();
+        let failing = || -> Result<()> {
+            let _guard = acquire(&store, "abc", 1)?;
+            anyhow::bail!("inspection failed")
+        };
+        assert!(failing().is_err());
+        assert_eq!(inflight(&store), 0);
+    }
+}
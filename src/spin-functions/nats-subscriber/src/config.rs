@@ -0,0 +1,722 @@
+// Runtime configuration sourced from Spin application variables.
+// Every variable read here must also be declared in spin.toml.
+
+use anyhow::{Context, Result};
+use spin_common::variables::parse;
+use std::collections::HashMap;
+
+pub use spin_common::variables::{SpinVariables, Variables};
+
+use crate::control::DEFAULT_CONTROL_SUBJECT;
+use crate::detectors;
+use crate::envelope::Envelope;
+use crate::policy::{Patterns, Policy};
+use crate::quarantine;
+use crate::siem::LogFormat;
+use crate::signature::TrustedInspectionLevel;
+use crate::subject;
+use crate::warmup;
+
+/// How a batch whose every item was dropped is reported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AllDroppedResponse {
+    /// The per-item array of drops, like any other batch.
+    #[default]
+    Results,
+    /// An empty `204`.
+    NoContent,
+    /// `200` with `{"forward": 0, "dropped": N}`.
+    Summary,
+}
+
+impl std::str::FromStr for AllDroppedResponse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "results" => Ok(AllDroppedResponse::Results),
+            "204" => Ok(AllDroppedResponse::NoContent),
+            "summary" => Ok(AllDroppedResponse::Summary),
+            other => anyhow::bail!(
+                "unknown all_dropped_response '{}', expected 'results', '204' or 'summary'",
+                other
+            ),
+        }
+    }
+}
+
+/// What KV-dependent checks (concurrency limits, redaction limits,
+/// cumulative state) decide when the key/value store is unreachable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KvFailureMode {
+    /// Carry on as if the check passed.
+    #[default]
+    FailOpen,
+    /// Drop the message.
+    FailClosed,
+}
+
+impl std::str::FromStr for KvFailureMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fail_open" => Ok(KvFailureMode::FailOpen),
+            "fail_closed" => Ok(KvFailureMode::FailClosed),
+            other => anyhow::bail!("unknown kv_failure_mode '{}', expected 'fail_open' or 'fail_closed'", other),
+        }
+    }
+}
+
+/// Default cap on request bodies accepted by the batch endpoint.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// Default first sequence of a conversation.
+pub const DEFAULT_SEQUENCE_START: u64 = 1;
+
+/// Default `Retry-After` sent with backpressure, in seconds.
+pub const DEFAULT_BACKPRESSURE_RETRY_AFTER_SECS: u64 = 1;
+
+/// Default number of recent inspections the latency SLO averages over.
+pub const DEFAULT_SLO_WINDOW: usize = 20;
+
+/// Default wait for a missing token before reassembly skips it.
+pub const DEFAULT_GAP_TIMEOUT_MS: u64 = 2000;
+
+/// Default window for coalescing repeated drop reasons in logs.
+pub const DEFAULT_LOG_DEBOUNCE_MS: u64 = 60_000;
+
+/// Settings resolved once per request.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Policy applied when a request names no policy or an unknown one.
+    pub default_policy: Policy,
+    /// Named policies from the `policies` variable, a JSON object of
+    /// `{ "name": { ...policy fields... } }`.
+    pub policies: HashMap<String, Policy>,
+    /// Subject patterns mapped to the policy applied to their messages when
+    /// a request names none, from the `subject_policies` variable, e.g.
+    /// `support.>=strict, marketing.>=lax`. The first match wins.
+    pub subject_policies: Vec<(String, String)>,
+    /// Policy evaluated in shadow alongside the live one, from the
+    /// `shadow_policy` variable (JSON policy fields); messages where its
+    /// verdict differs are logged and the response is unaffected.
+    pub shadow_policy: Option<Policy>,
+    /// Largest request body, in bytes, the batch endpoint will parse.
+    pub max_body_bytes: usize,
+    /// Sequences that end the SSE stream when they appear in forwarded
+    /// content, from the `stop_sequences` variable (a JSON array of strings).
+    pub stop_sequences: Vec<String>,
+    /// Fraction (0.0-1.0) of sequenced tokens to inspect in full; the rest
+    /// only go through the detectors that drop content. 1.0 inspects
+    /// everything.
+    pub sample_rate: f64,
+    /// Cap on concurrent requests per conversation; unlimited when unset.
+    pub max_inflight_per_conversation: Option<u64>,
+    /// Answer `503` with `Retry-After` once this many requests are in
+    /// flight across all conversations; unlimited when unset.
+    pub backpressure_max_inflight: Option<u64>,
+    /// Answer `503` with `Retry-After` when counting the request in KV
+    /// takes longer than this, in milliseconds; unchecked when unset.
+    pub backpressure_max_kv_latency_ms: Option<u64>,
+    /// `Retry-After` sent with backpressure, in seconds.
+    pub backpressure_retry_after_secs: u64,
+    /// Average inspection latency, in milliseconds, above which low-risk
+    /// subjects are shed onto the fast path; see `slo`. Unchecked when
+    /// unset.
+    pub slo_target_ms: Option<u64>,
+    /// Number of recent inspections the latency SLO averages over.
+    pub slo_window: usize,
+    /// NATS subject patterns (wildcards allowed) of low-risk messages that
+    /// get reduced inspection while the latency SLO is breached.
+    pub slo_fast_path_subjects: Vec<String>,
+    /// NATS subject patterns (wildcards allowed) whose messages are trusted
+    /// and allowed without inspection, from a comma-separated list.
+    pub bypass_subjects: Vec<String>,
+    /// How long the SSE reassembly buffer waits for a missing token before
+    /// skipping past it, in milliseconds.
+    pub gap_timeout_ms: u64,
+    /// How long after a gap is skipped a late token from it is still sent,
+    /// as an out-of-order correction. Zero discards late tokens.
+    pub late_grace_ms: u64,
+    /// How long a token may wait in the reassembly buffer before `gc`
+    /// evicts it; unlimited when unset.
+    pub message_ttl_ms: Option<u64>,
+    /// Budget for content held in reassembly buffers across all
+    /// conversations; the least recently active are evicted beyond it.
+    pub reassembly_max_bytes: Option<usize>,
+    /// Most missing sequences the reassembly buffer waits out; a wider gap
+    /// is skipped at once. Unset waits out any gap.
+    pub max_sequence_gap: Option<u64>,
+    /// Sequence of a conversation's first token; producers that count from
+    /// zero set it to 0.
+    pub sequence_start: u64,
+    /// Resume a reconnecting stream after the sequence in its
+    /// `Last-Event-ID` header instead of from the start.
+    pub resume_streams: bool,
+    /// URL of a translation service; when set, plain-text content is
+    /// translated to English before inspection.
+    pub translate_before_inspect: Option<String>,
+    /// OTLP/HTTP collector base URL for trace export; tracing is off when
+    /// unset.
+    pub otlp_endpoint: Option<String>,
+    /// Also inspect the message subject, dropping messages whose subject is
+    /// malformed (control characters, whitespace, wildcards) or contains an
+    /// injection pattern.
+    pub inspect_subject: bool,
+    /// Shape of the JSON response around inspection results.
+    pub envelope: Envelope,
+    /// Window, in milliseconds, within which repeated drop reasons for a
+    /// conversation are counted instead of logged. Zero logs every drop.
+    pub log_debounce_ms: u64,
+    /// Plain-text messages carry the full text so far rather than a delta;
+    /// only the newly appended content is inspected.
+    pub cumulative_content: bool,
+    /// Include `content_sha256` of the forward content in results.
+    pub content_digest: bool,
+    /// Terminate a conversation's stream once it has had more redactions
+    /// than this; unlimited when unset.
+    pub max_redactions_per_conversation: Option<u64>,
+    /// Terminate a conversation's stream once it has forwarded more than
+    /// this many bytes of content; unlimited when unset.
+    pub max_conversation_bytes: Option<u64>,
+    /// Inspect every token of a conversation under the policy in effect at
+    /// its first token, ignoring reloads until its stream closes.
+    pub seal_policy: bool,
+    /// Add a `Server-Timing` header breaking down parse, inspect and
+    /// forward durations.
+    pub server_timing: bool,
+    /// Add `x-body-bytes` and `x-data-bytes` headers with the raw request
+    /// body and decoded `data` lengths, to size the envelope overhead.
+    pub report_lengths: bool,
+    /// Add an `x-inspection-engine` header naming the engine version and
+    /// the applied policy's ruleset hash. Left off a batch whose items map
+    /// to different policies.
+    pub engine_header: bool,
+    /// Subject whose messages reload a policy instead of being inspected.
+    pub control_subject: String,
+    /// Key reload messages must be signed with; reloads are refused when
+    /// unset.
+    pub control_key: Option<String>,
+    /// Prefix of the request/reply inboxes verdicts are routed back to.
+    pub inbox_prefix: String,
+    /// Response to a batch in which every item was dropped.
+    pub all_dropped_response: AllDroppedResponse,
+    /// HTTP-to-NATS bridge through which inspected messages are also
+    /// published to `chat.{id}.inspected`; no fanout when unset.
+    pub fanout_bridge_url: Option<String>,
+    /// Send a content-free `blocked` SSE frame for dropped tokens instead
+    /// of dropping them silently.
+    pub notify_blocked: bool,
+    /// Time budget for a batch; past it the results so far are returned
+    /// marked `truncated`. Unlimited when unset.
+    pub batch_deadline_ms: Option<u64>,
+    /// Whether KV-dependent checks allow or drop when KV is unreachable.
+    pub kv_failure_mode: KvFailureMode,
+    /// Format of drop and redact event log lines.
+    pub log_format: LogFormat,
+    /// Shared key trusted producers sign messages with; no message is
+    /// trusted when unset.
+    pub signing_key: Option<String>,
+    /// Inspection given to messages with a valid signature.
+    pub trusted_inspection_level: TrustedInspectionLevel,
+    /// Drop messages timestamped further than this ahead of our clock;
+    /// timestamps aren't checked when unset.
+    pub max_future_skew_secs: Option<u64>,
+    /// The first this many tokens of each conversation are inspected with
+    /// relaxed injection rules; see `warmup`.
+    pub warmup_tokens: u64,
+    /// Injection patterns not applied during warmup, from the
+    /// comma-separated `warmup_relaxed_patterns` variable.
+    pub warmup_relaxed_patterns: Vec<String>,
+    /// HTTP-to-NATS bridge through which the gateway publishes each
+    /// conversation's summary to `chat.{id}.summary` when its stream
+    /// closes; verdicts aren't tallied when unset.
+    pub summary_bridge_url: Option<String>,
+    /// Serve debugging endpoints such as `/inspect/explain`.
+    pub debug_endpoints: bool,
+    /// HTTP-to-NATS bridge through which the originals of redacted messages
+    /// are published, sealed, to `quarantine.{id}`; see `quarantine`.
+    pub quarantine_bridge_url: Option<String>,
+    /// Base64 AES-256 key quarantined originals are sealed with.
+    pub quarantine_key: Option<String>,
+    /// HTTP-to-NATS bridge through which fingerprints of redacted secrets
+    /// are published to `inspection.correlation`; see `correlation`.
+    pub correlation_bridge_url: Option<String>,
+    /// Key of the HMAC fingerprinting redacted secrets.
+    pub correlation_salt: Option<String>,
+    /// Key of `redaction_hash` placeholders, given to every policy.
+    /// Placeholders only match across deployments sharing it.
+    pub redaction_hash_key: Option<String>,
+    /// Load the canary tokens stored in KV with each policy, for the
+    /// `canary` detector to drop content carrying one; see `canary`.
+    pub canary_tokens: bool,
+    /// HTTP-to-NATS bridge through which high-priority alerts, such as a
+    /// canary firing, are published to `alerts`.
+    pub alert_bridge_url: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            default_policy: Policy::default(),
+            policies: HashMap::new(),
+            subject_policies: Vec::new(),
+            shadow_policy: None,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            stop_sequences: Vec::new(),
+            sample_rate: 1.0,
+            max_inflight_per_conversation: None,
+            backpressure_max_inflight: None,
+            backpressure_max_kv_latency_ms: None,
+            backpressure_retry_after_secs: DEFAULT_BACKPRESSURE_RETRY_AFTER_SECS,
+            slo_target_ms: None,
+            slo_window: DEFAULT_SLO_WINDOW,
+            slo_fast_path_subjects: Vec::new(),
+            bypass_subjects: Vec::new(),
+            gap_timeout_ms: DEFAULT_GAP_TIMEOUT_MS,
+            late_grace_ms: 0,
+            message_ttl_ms: None,
+            reassembly_max_bytes: None,
+            max_sequence_gap: None,
+            sequence_start: DEFAULT_SEQUENCE_START,
+            resume_streams: true,
+            translate_before_inspect: None,
+            otlp_endpoint: None,
+            inspect_subject: false,
+            envelope: Envelope::Bare,
+            log_debounce_ms: DEFAULT_LOG_DEBOUNCE_MS,
+            cumulative_content: false,
+            content_digest: false,
+            max_redactions_per_conversation: None,
+            max_conversation_bytes: None,
+            seal_policy: false,
+            server_timing: false,
+            report_lengths: false,
+            engine_header: false,
+            control_subject: DEFAULT_CONTROL_SUBJECT.to_string(),
+            control_key: None,
+            inbox_prefix: subject::DEFAULT_INBOX_PREFIX.to_string(),
+            all_dropped_response: AllDroppedResponse::Results,
+            fanout_bridge_url: None,
+            notify_blocked: false,
+            batch_deadline_ms: None,
+            kv_failure_mode: KvFailureMode::FailOpen,
+            log_format: LogFormat::Json,
+            signing_key: None,
+            trusted_inspection_level: TrustedInspectionLevel::Full,
+            max_future_skew_secs: None,
+            warmup_tokens: 0,
+            warmup_relaxed_patterns: warmup::DEFAULT_RELAXED_PATTERNS.iter().map(|p| p.to_string()).collect(),
+            summary_bridge_url: None,
+            debug_endpoints: false,
+            quarantine_bridge_url: None,
+            quarantine_key: None,
+            correlation_bridge_url: None,
+            correlation_salt: None,
+            redaction_hash_key: None,
+            canary_tokens: false,
+            alert_bridge_url: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(vars: &dyn Variables) -> Result<Self> {
+        let mut settings = Settings::default();
+
+        if let Some(raw) = vars.get("default_policy") {
+            settings.default_policy =
+                serde_json::from_str(&raw).context("invalid `default_policy` variable")?;
+        }
+        if let Some(posture) = parse(vars, "posture")? {
+            settings.default_policy.posture = posture;
+        }
+        if let Some(xss_protection) = parse(vars, "xss_protection")? {
+            settings.default_policy.xss_protection = xss_protection;
+        }
+        if let Some(max_matches) = parse(vars, "max_matches")? {
+            settings.default_policy.max_matches = Some(max_matches);
+        }
+        if let Some(pii_ssn) = parse(vars, "pii_ssn")? {
+            settings.default_policy.pii_ssn = pii_ssn;
+        }
+        if let Some(pii_ner) = parse(vars, "pii_ner")? {
+            settings.default_policy.pii_ner = pii_ner;
+        }
+        if let Some(max_reason_chars) = parse(vars, "max_reason_chars")? {
+            settings.default_policy.max_reason_chars = Some(max_reason_chars);
+        }
+        if let Some(max_char_run) = parse(vars, "max_char_run")? {
+            settings.default_policy.max_char_run = Some(max_char_run);
+        }
+        if let Some(inspect_attachments) = parse(vars, "inspect_attachments")? {
+            settings.default_policy.inspect_attachments = inspect_attachments;
+        }
+        if let Some(scrub_stack_traces) = parse(vars, "scrub_stack_traces")? {
+            settings.default_policy.scrub_stack_traces = scrub_stack_traces;
+        }
+        let suspicious_link_patterns = list(vars, "suspicious_link_patterns");
+        if !suspicious_link_patterns.is_empty() {
+            settings.default_policy.suspicious_link_patterns = Patterns::case_insensitive(suspicious_link_patterns)
+                .context("invalid `suspicious_link_patterns` variable")?;
+        }
+        if let Some(normalize_leet) = parse(vars, "normalize_leet")? {
+            settings.default_policy.normalize_leet = normalize_leet;
+        }
+        if let Some(max_decode_depth) = parse(vars, "max_decode_depth")? {
+            settings.default_policy.max_decode_depth = max_decode_depth;
+        }
+        if let Some(algorithm) = parse(vars, "redaction_hash")? {
+            settings.default_policy.redaction_hash = Some(algorithm);
+        }
+        let detector_order = detector_names(vars, "detector_order")?;
+        if !detector_order.is_empty() {
+            settings.default_policy.detector_order = detector_order;
+        }
+        let enabled_detectors = detector_names(vars, "detectors")?;
+        if !enabled_detectors.is_empty() {
+            settings.default_policy.enabled_detectors = enabled_detectors;
+        }
+        if let Some(short_circuit) = parse(vars, "short_circuit_on_drop")? {
+            settings.default_policy.short_circuit_on_drop = short_circuit;
+        }
+        let highlight_detectors = detector_names(vars, "highlight_detectors")?;
+        if !highlight_detectors.is_empty() {
+            settings.default_policy.highlight_detectors = highlight_detectors;
+        }
+        if let Some(raw) = vars.get("policies") {
+            settings.policies =
+                serde_json::from_str(&raw).context("invalid `policies` variable")?;
+        }
+        if let Some(raw) = vars.get("shadow_policy") {
+            settings.shadow_policy =
+                Some(serde_json::from_str(&raw).context("invalid `shadow_policy` variable")?);
+        }
+        for mapping in list(vars, "subject_policies") {
+            let Some((pattern, name)) = mapping.split_once('=') else {
+                anyhow::bail!("invalid `subject_policies` variable: expected `pattern=policy`, got '{}'", mapping);
+            };
+            settings.subject_policies.push((pattern.trim().to_string(), name.trim().to_string()));
+        }
+        if let Some(value) = parse(vars, "max_body_bytes")? {
+            settings.max_body_bytes = value;
+        }
+        if let Some(raw) = vars.get("stop_sequences") {
+            settings.stop_sequences =
+                serde_json::from_str(&raw).context("invalid `stop_sequences` variable")?;
+        }
+        if let Some(value) = parse::<f64>(vars, "sample_rate")? {
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&value),
+                "`sample_rate` must be between 0.0 and 1.0, got {}",
+                value
+            );
+            settings.sample_rate = value;
+        }
+        settings.max_inflight_per_conversation = parse(vars, "max_inflight_per_conversation")?;
+        settings.backpressure_max_inflight = parse(vars, "backpressure_max_inflight")?;
+        settings.backpressure_max_kv_latency_ms = parse(vars, "backpressure_max_kv_latency_ms")?;
+        if let Some(value) = parse(vars, "backpressure_retry_after_secs")? {
+            settings.backpressure_retry_after_secs = value;
+        }
+        settings.slo_target_ms = parse(vars, "slo_target_ms")?;
+        if let Some(value) = parse(vars, "slo_window")? {
+            settings.slo_window = value;
+        }
+        settings.slo_fast_path_subjects = list(vars, "slo_fast_path_subjects");
+        settings.bypass_subjects = list(vars, "bypass_subjects");
+        if let Some(value) = parse(vars, "gap_timeout_ms")? {
+            settings.gap_timeout_ms = value;
+        }
+        if let Some(value) = parse(vars, "late_grace_ms")? {
+            settings.late_grace_ms = value;
+        }
+        settings.message_ttl_ms = parse(vars, "message_ttl_ms")?;
+        settings.reassembly_max_bytes = parse(vars, "reassembly_max_bytes")?;
+        settings.max_sequence_gap = parse(vars, "max_sequence_gap")?;
+        if let Some(value) = parse(vars, "sequence_start")? {
+            settings.sequence_start = value;
+        }
+        if let Some(value) = parse(vars, "resume_streams")? {
+            settings.resume_streams = value;
+        }
+        settings.translate_before_inspect = vars.get("translate_before_inspect");
+        settings.otlp_endpoint = vars.get("otlp_endpoint");
+        if let Some(value) = parse(vars, "inspect_subject")? {
+            settings.inspect_subject = value;
+        }
+        if let Some(envelope) = parse(vars, "envelope")? {
+            settings.envelope = envelope;
+        }
+        if let Some(value) = parse(vars, "log_debounce_ms")? {
+            settings.log_debounce_ms = value;
+        }
+        if let Some(value) = parse(vars, "cumulative_content")? {
+            settings.cumulative_content = value;
+        }
+        if let Some(value) = parse(vars, "content_digest")? {
+            settings.content_digest = value;
+        }
+        settings.max_redactions_per_conversation = parse(vars, "max_redactions_per_conversation")?;
+        settings.max_conversation_bytes = parse(vars, "max_conversation_bytes")?;
+        if let Some(value) = parse(vars, "seal_policy")? {
+            settings.seal_policy = value;
+        }
+        if let Some(value) = parse(vars, "server_timing")? {
+            settings.server_timing = value;
+        }
+        if let Some(value) = parse(vars, "report_lengths")? {
+            settings.report_lengths = value;
+        }
+        if let Some(value) = parse(vars, "engine_header")? {
+            settings.engine_header = value;
+        }
+        if let Some(control_subject) = vars.get("control_subject") {
+            if let Some(reason) = subject::suspicious(&control_subject) {
+                anyhow::bail!("invalid `control_subject` variable: {}", reason);
+            }
+            settings.control_subject = control_subject;
+        }
+        settings.control_key = vars.get("control_key");
+        if let Some(inbox_prefix) = vars.get("inbox_prefix") {
+            if let Some(reason) = subject::suspicious(&inbox_prefix) {
+                anyhow::bail!("invalid `inbox_prefix` variable: {}", reason);
+            }
+            settings.inbox_prefix = inbox_prefix;
+        }
+        if let Some(response) = parse(vars, "all_dropped_response")? {
+            settings.all_dropped_response = response;
+        }
+        settings.fanout_bridge_url = vars.get("fanout_bridge_url");
+        if let Some(value) = parse(vars, "notify_blocked")? {
+            settings.notify_blocked = value;
+        }
+        settings.batch_deadline_ms = parse(vars, "batch_deadline_ms")?;
+        if let Some(mode) = parse(vars, "kv_failure_mode")? {
+            settings.kv_failure_mode = mode;
+        }
+        if let Some(format) = parse(vars, "log_format")? {
+            settings.log_format = format;
+        }
+        settings.signing_key = vars.get("signing_key");
+        if let Some(level) = parse(vars, "trusted_inspection_level")? {
+            settings.trusted_inspection_level = level;
+        }
+        settings.max_future_skew_secs = parse(vars, "max_future_skew_secs")?;
+        if let Some(value) = parse(vars, "warmup_tokens")? {
+            settings.warmup_tokens = value;
+        }
+        let warmup_relaxed_patterns = list(vars, "warmup_relaxed_patterns");
+        if !warmup_relaxed_patterns.is_empty() {
+            settings.warmup_relaxed_patterns = warmup_relaxed_patterns;
+        }
+        settings.summary_bridge_url = vars.get("summary_bridge_url");
+        if let Some(value) = parse(vars, "debug_endpoints")? {
+            settings.debug_endpoints = value;
+        }
+        settings.quarantine_bridge_url = vars.get("quarantine_bridge_url");
+        settings.quarantine_key = vars.get("quarantine_key");
+        if let Some(key) = &settings.quarantine_key {
+            quarantine::parse_key(key).context("invalid `quarantine_key` variable")?;
+        }
+        if settings.quarantine_bridge_url.is_some() && settings.quarantine_key.is_none() {
+            anyhow::bail!("`quarantine_bridge_url` is set without a `quarantine_key`");
+        }
+        settings.correlation_bridge_url = vars.get("correlation_bridge_url");
+        settings.correlation_salt = vars.get("correlation_salt");
+        if settings.correlation_bridge_url.is_some() && settings.correlation_salt.is_none() {
+            anyhow::bail!("`correlation_bridge_url` is set without a `correlation_salt`");
+        }
+        if let Some(value) = parse(vars, "canary_tokens")? {
+            settings.canary_tokens = value;
+        }
+        settings.alert_bridge_url = vars.get("alert_bridge_url");
+        settings.redaction_hash_key = vars.get("redaction_hash_key");
+        let shadow_policy = settings.shadow_policy.as_mut();
+        for policy in std::iter::once(&mut settings.default_policy).chain(settings.policies.values_mut()).chain(shadow_policy) {
+            if policy.redaction_hash.is_some() && settings.redaction_hash_key.is_none() {
+                anyhow::bail!("`redaction_hash` is set without a `redaction_hash_key`");
+            }
+            policy.redaction_hash_key = settings.redaction_hash_key.clone();
+        }
+        let named = settings.policies.values().map(|policy| ("policies", policy));
+        for (name, policy) in named.chain(settings.shadow_policy.iter().map(|policy| ("shadow_policy", policy))) {
+            for names in [&policy.detector_order, &policy.enabled_detectors, &policy.highlight_detectors] {
+                check_detectors(name, names)?;
+            }
+        }
+
+        Ok(settings)
+    }
+}
+
+/// A comma-separated list of detector names, all of which must exist.
+fn detector_names(vars: &dyn Variables, name: &str) -> Result<Vec<String>> {
+    let names = list(vars, name);
+    check_detectors(name, &names)?;
+    Ok(names)
+}
+
+/// Fail on the first of `names`, read from variable `name`, that isn't a
+/// detector.
+fn check_detectors(name: &str, names: &[String]) -> Result<()> {
+    if let Some(unknown) = names.iter().find(|n| !detectors::exists(n)) {
+        anyhow::bail!("invalid `{}` variable: unknown detector '{}'", name, unknown);
+    }
+    Ok(())
+}
+
+/// Split a comma-separated variable into its trimmed, non-empty items.
+fn list(vars: &dyn Variables, name: &str) -> Vec<String> {
+    vars.get(name)
+        .map(|raw| {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_defaults_when_unset() {
+        let settings = Settings::load(&HashMap::new()).unwrap();
+        assert_eq!(settings.default_policy, Policy::default());
+        assert!(settings.policies.is_empty());
+    }
+
+    #[test]
+    fn test_load_named_policies() {
+        let vars = HashMap::from([("policies", r#"{"strict": {"sensitive_patterns": ["internal"]}}"#)]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.policies["strict"].sensitive_patterns, vec!["internal"]);
+    }
+
+    #[test]
+    fn test_load_shadow_policy() {
+        assert_eq!(Settings::load(&HashMap::new()).unwrap().shadow_policy, None);
+        let vars = HashMap::from([("shadow_policy", r#"{"pii_ner": true}"#)]);
+        assert!(Settings::load(&vars).unwrap().shadow_policy.unwrap().pii_ner);
+        let vars = HashMap::from([("shadow_policy", r#"{"pii_ner": true}"#), ("redaction_hash_key", "k3y")]);
+        let shadow_policy = Settings::load(&vars).unwrap().shadow_policy.unwrap();
+        assert_eq!(shadow_policy.redaction_hash_key.as_deref(), Some("k3y"));
+        let vars = HashMap::from([("shadow_policy", "not json")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_subject_policies() {
+        let vars = HashMap::from([("subject_policies", "support.> = strict, marketing.*=lax")]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(
+            settings.subject_policies,
+            [("support.>".to_string(), "strict".to_string()), ("marketing.*".to_string(), "lax".to_string())]
+        );
+
+        let vars = HashMap::from([("subject_policies", "support.>")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_max_body_bytes() {
+        let settings = Settings::load(&HashMap::new()).unwrap();
+        assert_eq!(settings.max_body_bytes, DEFAULT_MAX_BODY_BYTES);
+
+        let vars = HashMap::from([("max_body_bytes", "2048")]);
+        assert_eq!(Settings::load(&vars).unwrap().max_body_bytes, 2048);
+    }
+
+    #[test]
+    fn test_load_stop_sequences() {
+        let vars = HashMap::from([("stop_sequences", r#"["<|end|>", "\n\nUser:"]"#)]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.stop_sequences, vec!["<|end|>", "\n\nUser:"]);
+    }
+
+    #[test]
+    fn test_load_redaction_hash_needs_key() {
+        let vars = HashMap::from([("redaction_hash", "blake3")]);
+        assert!(Settings::load(&vars).is_err());
+        let vars = HashMap::from([("policies", r#"{"hashed": {"redaction_hash": "sha256"}}"#)]);
+        assert!(Settings::load(&vars).is_err());
+
+        let vars = HashMap::from([("redaction_hash", "blake3"), ("redaction_hash_key", "k3y")]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.default_policy.redaction_hash_key.as_deref(), Some("k3y"));
+    }
+
+    #[test]
+    fn test_load_rejects_invalid_link_pattern() {
+        let vars = HashMap::from([("suspicious_link_patterns", "evil\\.example,bad([")]);
+        assert!(Settings::load(&vars).is_err());
+        let vars = HashMap::from([("suspicious_link_patterns", "evil\\.example")]);
+        assert!(Settings::load(&vars).unwrap().default_policy.suspicious_link_patterns.find("evil.example").is_some());
+    }
+
+    #[test]
+    fn test_load_control_subject() {
+        let vars = HashMap::from([("control_subject", "tenant-a.control.reload")]);
+        assert_eq!(Settings::load(&vars).unwrap().control_subject, "tenant-a.control.reload");
+        let vars = HashMap::from([("control_subject", "tenant-a.control.>")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_detector_order() {
+        let vars = HashMap::from([("detector_order", "keyword, injection")]);
+        assert_eq!(Settings::load(&vars).unwrap().default_policy.detector_order, vec!["keyword", "injection"]);
+        let vars = HashMap::from([("detector_order", "keyword,entropy")]);
+        assert!(Settings::load(&vars).is_err());
+
+        // Named and shadow policies are checked too
+        let vars = HashMap::from([("policies", r#"{"strict": {"detector_order": ["jwt", "entropy"]}}"#)]);
+        let err = Settings::load(&vars).unwrap_err();
+        assert!(err.to_string().contains("unknown detector 'entropy'"), "{}", err);
+        let vars = HashMap::from([("shadow_policy", r#"{"highlight_detectors": ["nope"]}"#)]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_enabled_detectors() {
+        let vars = HashMap::from([("detectors", "keyword,jwt")]);
+        assert_eq!(Settings::load(&vars).unwrap().default_policy.enabled_detectors, vec!["keyword", "jwt"]);
+        let vars = HashMap::from([("detectors", "keyword,luhn")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_sample_rate() {
+        assert_eq!(Settings::load(&HashMap::new()).unwrap().sample_rate, 1.0);
+        let vars = HashMap::from([("sample_rate", "0.25")]);
+        assert_eq!(Settings::load(&vars).unwrap().sample_rate, 0.25);
+        let vars = HashMap::from([("sample_rate", "1.5")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+
+    #[test]
+    fn test_load_posture_applies_to_default_policy() {
+        let vars = HashMap::from([("posture", "deny")]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.default_policy.posture, crate::policy::Posture::Deny);
+        assert!(Settings::load(&HashMap::from([("posture", "maybe")])).is_err());
+    }
+
+    #[test]
+    fn test_load_bypass_subjects() {
+        let vars = HashMap::from([("bypass_subjects", "chat.*.system, internal.>,")]);
+        let settings = Settings::load(&vars).unwrap();
+        assert_eq!(settings.bypass_subjects, vec!["chat.*.system", "internal.>"]);
+    }
+
+    #[test]
+    fn test_load_rejects_malformed_policies() {
+        let vars = HashMap::from([("policies", "not json")]);
+        assert!(Settings::load(&vars).is_err());
+    }
+}
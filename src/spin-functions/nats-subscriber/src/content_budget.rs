@@ -0,0 +1,83 @@
+// Per-conversation content budget. A generation that never stops is a bug
+// or an abuse of the model, so once a conversation has forwarded more than
+// `max_conversation_bytes` of content the stream is terminated: the token
+// that crosses the budget and every later one is dropped with
+// `BUDGET_EXCEEDED`, and the gateway ends the stream on seeing it.
+
+use anyhow::Result;
+
+use crate::kv::{self, Store};
+use crate::{Action, InspectionResult};
+
+pub const REASON_CODE: &str = "BUDGET_EXCEEDED";
+
+fn key(scope: &str) -> String {
+    format!("content_bytes/{}", scope)
+}
+
+/// Count the bytes `result` forwards against the conversation's budget of
+/// `max`, returning the drop that replaces it once the budget is exceeded.
+pub fn check(
+    store: &dyn Store,
+    scope: &str,
+    max: u64,
+    original: &str,
+    result: &InspectionResult,
+) -> Result<Option<InspectionResult>> {
+    let key = key(scope);
+    let mut used = kv::read_counter(store, &key)?;
+    if used <= max {
+        let bytes = result.forward_content(original).map_or(0, str::len);
+        if bytes > 0 {
+            used = kv::add_counter(store, &key, bytes as i64)?;
+        }
+    }
+    if used <= max {
+        return Ok(None);
+    }
+
+    let mut terminated = InspectionResult::drop(format!(
+        "Conversation exceeded its {} byte content budget; stream terminated",
+        max
+    ))
+    .with_reason_code(REASON_CODE);
+    if result.action != Action::Allow {
+        terminated.secondary_actions = vec![result.action];
+    }
+    Ok(Some(terminated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    fn apply(store: &MemoryStore, scope: &str, max: u64, content: &str) -> InspectionResult {
+        let result = InspectionResult::allow();
+        check(store, scope, max, content, &result).unwrap().unwrap_or(result)
+    }
+
+    #[test]
+    fn test_budget_crossed_mid_stream() {
+        let store = MemoryStore::default();
+        assert_eq!(apply(&store, "abc", 10, "Hello").action, Action::Allow);
+        assert_eq!(apply(&store, "abc", 10, " you").action, Action::Allow);
+
+        // 14 bytes would go past the budget of 10
+        let result = apply(&store, "abc", 10, " there");
+        assert_eq!(result.action, Action::Drop);
+        assert_eq!(result.reason_code.as_deref(), Some(REASON_CODE));
+        assert_eq!(apply(&store, "abc", 10, "!").reason_code.as_deref(), Some(REASON_CODE));
+
+        // Other conversations have their own budget
+        assert_eq!(apply(&store, "xyz", 10, "Hello").action, Action::Allow);
+    }
+
+    #[test]
+    fn test_dropped_content_not_counted() {
+        let store = MemoryStore::default();
+        let dropped = InspectionResult::drop("injection".into());
+        assert!(check(&store, "abc", 4, "ignore previous instructions", &dropped).unwrap().is_none());
+        assert_eq!(apply(&store, "abc", 4, "Hi").action, Action::Allow);
+    }
+}
@@ -0,0 +1,49 @@
+// Control channel. A message on the configured control subject reloads a
+// named policy instead of being inspected: its data is
+//
+//   {"name": "strict", "policy": { ...Policy fields... }}
+//
+// and the policy is written to KV, where `policy::select` picks it up on the
+// next request. Reloads must be signed (see `signature`) with the secret
+// `control_key`, and are refused outright while it is unset. Deployments
+// sharing a bridge also give each tenant its own control subject and key so
+// one tenant can't reload another's policies.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::kv::Store;
+use crate::policy::{self, Policy};
+use crate::signature;
+
+/// Control subject used unless `control_subject` is set.
+pub const DEFAULT_CONTROL_SUBJECT: &str = "inspection.control.reload";
+
+pub const REASON_CODE: &str = "RELOAD_UNAUTHORIZED";
+
+/// Check a reload on `subject` carries a valid signature under `key`.
+pub fn authorize(key: Option<&str>, subject: &str, data: &str, signature: Option<&str>) -> Result<()> {
+    let Some(key) = key else {
+        anyhow::bail!("policy reloads are disabled: `control_key` is not set");
+    };
+    let Some(signature) = signature else {
+        anyhow::bail!("reload message is not signed");
+    };
+    anyhow::ensure!(signature::verify(key, subject, data, signature), "reload message signature is invalid");
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct Reload {
+    name: String,
+    policy: Policy,
+}
+
+/// Apply a reload message, returning the name of the reloaded policy.
+pub fn reload(data: &str, store: &dyn Store) -> Result<String> {
+    let reload: Reload = serde_json::from_str(data).context("invalid reload message")?;
+    let name = reload.name.trim();
+    anyhow::ensure!(!name.is_empty(), "reload message has no policy name");
+    policy::save(name, &reload.policy, store)?;
+    Ok(name.to_string())
+}
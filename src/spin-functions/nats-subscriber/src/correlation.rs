@@ -0,0 +1,92 @@
+// Cross-conversation secret correlation. The same secret redacted in many
+// conversations points to a widespread leak, so with
+// `correlation_bridge_url` set each redacted secret is fingerprinted and
+// `{fingerprint, conversation_id}` is published to `inspection.correlation`
+// for downstream detection. The fingerprint is an HMAC-SHA256 keyed with the
+// deployment's `correlation_salt`: stable across conversations, but without
+// the salt it can't be brute-forced back to a short secret. No plaintext
+// leaves the component.
+
+use std::collections::BTreeSet;
+
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::detectors;
+use crate::outbound::Outbound;
+use crate::policy::Policy;
+use crate::Action;
+
+pub const SUBJECT: &str = "inspection.correlation";
+
+/// Payload published for each redacted secret.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sighting {
+    pub fingerprint: String,
+    pub conversation_id: String,
+}
+
+/// Hex HMAC-SHA256 of `secret` under `salt`.
+pub fn fingerprint(salt: &str, secret: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(salt.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(secret.as_bytes());
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Distinct fingerprints of the secrets `policy` redacts in `content`: the
+/// whole match, even where only part of it is redacted, or, for findings
+/// without a span, the whole content.
+pub fn fingerprints(salt: &str, content: &str, policy: &Policy) -> BTreeSet<String> {
+    detectors::run(content, policy)
+        .iter()
+        .filter(|f| f.action == Action::Redact && f.confidence >= policy.min_confidence_for(f.detector))
+        .filter_map(|f| match f.match_span.as_ref().or(f.span.as_ref()) {
+            Some(span) => content.get(span.clone()),
+            None => Some(content),
+        })
+        .map(|secret| fingerprint(salt, secret))
+        .collect()
+}
+
+/// Publish a sighting of each secret redacted in `content`.
+pub fn publish(
+    outbound: &dyn Outbound,
+    bridge_url: &str,
+    salt: &str,
+    conversation_id: &str,
+    content: &str,
+    policy: &Policy,
+) -> Result<()> {
+    let url = format!("{}/publish/{}", bridge_url.trim_end_matches('/'), SUBJECT);
+    for fingerprint in fingerprints(salt, content, policy) {
+        let sighting = Sighting { fingerprint, conversation_id: conversation_id.to_string() };
+        let (status, _) = outbound.post(&url, "application/json", serde_json::to_vec(&sighting)?)?;
+        anyhow::ensure!((200..300).contains(&status), "bridge returned {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_is_salted() {
+        assert_eq!(fingerprint("salt", "hunter2"), fingerprint("salt", "hunter2"));
+        assert_ne!(fingerprint("salt", "hunter2"), fingerprint("pepper", "hunter2"));
+        assert_ne!(fingerprint("salt", "hunter2"), fingerprint("salt", "hunter3"));
+    }
+
+    #[test]
+    fn test_only_redacted_spans_fingerprinted() {
+        let policy = Policy::default();
+        let content = "my ssn is 123-45-6789";
+        assert_eq!(
+            fingerprints("salt", content, &policy),
+            BTreeSet::from([fingerprint("salt", "123-45-6789")])
+        );
+        assert!(fingerprints("salt", "hello there", &policy).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+// Log debouncing for drop reasons. A flood of messages tripping the same
+// pattern would otherwise log one identical line each; instead the first
+// occurrence per (conversation, reason code) is logged, repeats within the
+// window are counted, and the count is reported when the window has passed
+// and the reason occurs again.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::kv::Store;
+
+fn key(conversation_id: &str, reason_code: &str) -> String {
+    format!("logdebounce/{}/{}", conversation_id, reason_code)
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Window {
+    started_ms: u64,
+    suppressed: u64,
+}
+
+/// Decide what to log for one occurrence of `reason_code`, returning the
+/// lines to emit (none while suppressed). `line` is the log line for this
+/// occurrence. A zero window logs every occurrence.
+pub fn lines(
+    store: &dyn Store,
+    conversation_id: &str,
+    reason_code: &str,
+    line: String,
+    now_ms: u64,
+    window_ms: u64,
+) -> Result<Vec<String>> {
+    if window_ms == 0 {
+        return Ok(vec![line]);
+    }
+
+    let key = key(conversation_id, reason_code);
+    let window: Option<Window> = match store.get(&key)? {
+        Some(raw) => serde_json::from_slice(&raw).ok(),
+        None => None,
+    };
+
+    let mut out = Vec::new();
+    match window {
+        Some(mut window) if now_ms.saturating_sub(window.started_ms) < window_ms => {
+            window.suppressed += 1;
+            store.set(&key, &serde_json::to_vec(&window)?)?;
+            return Ok(out);
+        }
+        Some(window) if window.suppressed > 0 => out.push(format!(
+            "{} occurrences of {} suppressed for conversation {}",
+            window.suppressed, reason_code, conversation_id
+        )),
+        _ => {}
+    }
+    out.push(line);
+    store.set(&key, &serde_json::to_vec(&Window { started_ms: now_ms, suppressed: 0 })?)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    fn log(store: &MemoryStore, reason_code: &str, now_ms: u64) -> Vec<String> {
+        lines(store, "abc", reason_code, format!("dropped: {}", reason_code), now_ms, 1000).unwrap()
+    }
+
+    #[test]
+    fn test_repeats_coalesced_within_window() {
+        let store = MemoryStore::default();
+        assert_eq!(log(&store, "PROMPT_INJECTION", 0), vec!["dropped: PROMPT_INJECTION"]);
+        for now in 1..=5 {
+            assert!(log(&store, "PROMPT_INJECTION", now * 100).is_empty());
+        }
+        // A different reason code has its own window
+        assert_eq!(log(&store, "XSS", 600), vec!["dropped: XSS"]);
+
+        assert_eq!(
+            log(&store, "PROMPT_INJECTION", 1000),
+            vec![
+                "5 occurrences of PROMPT_INJECTION suppressed for conversation abc",
+                "dropped: PROMPT_INJECTION",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quiet_window_logs_without_summary() {
+        let store = MemoryStore::default();
+        assert_eq!(log(&store, "XSS", 0).len(), 1);
+        assert_eq!(log(&store, "XSS", 5000), vec!["dropped: XSS"]);
+    }
+
+    #[test]
+    fn test_zero_window_logs_everything() {
+        let store = MemoryStore::default();
+        for _ in 0..3 {
+            let out = lines(&store, "abc", "XSS", "dropped".into(), 0, 0).unwrap();
+            assert_eq!(out, vec!["dropped"]);
+        }
+    }
+}
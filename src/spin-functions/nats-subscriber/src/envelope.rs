@@ -0,0 +1,144 @@
+// Response envelopes. Consumers differ in the JSON shape they expect around
+// an `InspectionResult`, so the `envelope` variable picks one:
+//
+//   bare     the result itself (or an array of results for a batch)
+//   wrapped  `{"result": ...}`
+//   jsonapi  a JSON:API document whose resources hold the result fields as
+//            `attributes`, identified by sequence number when one is known
+
+use serde_json::{json, Map, Value};
+
+/// JSON:API resource type for inspection results.
+pub const RESOURCE_TYPE: &str = "inspection-result";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Envelope {
+    #[default]
+    Bare,
+    Wrapped,
+    JsonApi,
+}
+
+impl std::str::FromStr for Envelope {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "bare" => Ok(Envelope::Bare),
+            "wrapped" => Ok(Envelope::Wrapped),
+            "jsonapi" => Ok(Envelope::JsonApi),
+            other => anyhow::bail!("unknown envelope '{}', expected 'bare', 'wrapped' or 'jsonapi'", other),
+        }
+    }
+}
+
+impl Envelope {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Envelope::JsonApi => "application/vnd.api+json",
+            _ => "application/json",
+        }
+    }
+
+    /// Envelope a single serialized result.
+    pub fn single(self, result: Value, sequence: Option<u64>) -> Value {
+        match self {
+            Envelope::Bare => result,
+            Envelope::Wrapped => json!({ "result": result }),
+            Envelope::JsonApi => json!({ "data": resource(result, sequence) }),
+        }
+    }
+
+    /// Envelope a batch of serialized results, in order.
+    pub fn batch(self, results: Vec<(Value, Option<u64>)>) -> Value {
+        match self {
+            Envelope::Bare => Value::Array(results.into_iter().map(|(result, _)| result).collect()),
+            Envelope::Wrapped => {
+                json!({ "result": results.into_iter().map(|(result, _)| result).collect::<Vec<_>>() })
+            }
+            Envelope::JsonApi => json!({
+                "data": results
+                    .into_iter()
+                    .map(|(result, sequence)| resource(result, sequence))
+                    .collect::<Vec<_>>()
+            }),
+        }
+    }
+
+    /// Envelope the results of a batch cut short with `remaining` messages
+    /// uninspected. The results are marked `truncated`: bare batches become
+    /// `{"results": [...], "truncated": true, "remaining": N}`, wrapped ones
+    /// gain the two fields, and JSON:API documents carry them in `meta`.
+    pub fn truncated_batch(self, results: Vec<(Value, Option<u64>)>, remaining: usize) -> Value {
+        let mut body = match self.batch(results) {
+            Value::Array(results) => json!({ "results": results }),
+            body => body,
+        };
+        match self {
+            Envelope::JsonApi => body["meta"] = json!({ "truncated": true, "remaining": remaining }),
+            _ => {
+                body["truncated"] = true.into();
+                body["remaining"] = remaining.into();
+            }
+        }
+        body
+    }
+}
+
+fn resource(attributes: Value, sequence: Option<u64>) -> Value {
+    let mut resource = Map::new();
+    resource.insert("type".into(), RESOURCE_TYPE.into());
+    if let Some(sequence) = sequence {
+        resource.insert("id".into(), sequence.to_string().into());
+    }
+    resource.insert("attributes".into(), attributes);
+    Value::Object(resource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_shapes() {
+        let results = || vec![(json!({"action": "allow"}), Some(1)), (json!({"action": "drop"}), None)];
+        assert_eq!(
+            Envelope::Bare.batch(results()),
+            json!([{"action": "allow"}, {"action": "drop"}])
+        );
+        assert_eq!(
+            Envelope::Wrapped.batch(results()),
+            json!({"result": [{"action": "allow"}, {"action": "drop"}]})
+        );
+        assert_eq!(
+            Envelope::JsonApi.batch(results()),
+            json!({"data": [
+                {"type": RESOURCE_TYPE, "id": "1", "attributes": {"action": "allow"}},
+                {"type": RESOURCE_TYPE, "attributes": {"action": "drop"}},
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_truncated_batch_shapes() {
+        let results = || vec![(json!({"action": "allow"}), Some(1))];
+        assert_eq!(
+            Envelope::Bare.truncated_batch(results(), 3),
+            json!({"results": [{"action": "allow"}], "truncated": true, "remaining": 3})
+        );
+        assert_eq!(
+            Envelope::Wrapped.truncated_batch(results(), 3),
+            json!({"result": [{"action": "allow"}], "truncated": true, "remaining": 3})
+        );
+        assert_eq!(
+            Envelope::JsonApi.truncated_batch(results(), 3)["meta"],
+            json!({"truncated": true, "remaining": 3})
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("jsonapi".parse::<Envelope>().unwrap(), Envelope::JsonApi);
+        assert!("xml".parse::<Envelope>().is_err());
+    }
+}
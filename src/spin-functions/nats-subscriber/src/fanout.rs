@@ -0,0 +1,100 @@
+// Fanout of inspected tokens. With `fanout_bridge_url` set, every message
+// inspected on a `chat.{id}.*` subject is also published to
+// `chat.{id}.inspected` through the HTTP-to-NATS bridge, so gateways other
+// than SSE (e.g. WebSocket) can subscribe to one uniform, already-inspected
+// stream without knowing the SSE framing.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::outbound::Outbound;
+use crate::{subject, Action, InspectionResult, NatsMessage};
+
+/// Last subject token of the fanout subject.
+pub const INSPECTED_TOKEN: &str = "inspected";
+
+/// Payload published for each inspected message.
+#[derive(Debug, Serialize)]
+pub struct Inspected<'a> {
+    pub action: Action,
+    /// What to show: the original or redacted content, `None` when dropped.
+    pub content: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<&'a str>,
+}
+
+pub fn subject(conversation_id: &str) -> String {
+    format!("chat.{}.{}", conversation_id, INSPECTED_TOKEN)
+}
+
+/// Publish the inspected form of `message`. Messages without a conversation
+/// id, or already on a fanout subject, are not published.
+pub(crate) fn publish(outbound: &dyn Outbound, bridge_url: &str, message: &NatsMessage, result: &InspectionResult) -> Result<()> {
+    let Some(conversation_id) = subject::conversation_id(&message.subject) else {
+        return Ok(());
+    };
+    if message.subject.ends_with(&format!(".{}", INSPECTED_TOKEN)) {
+        return Ok(());
+    }
+
+    let payload = Inspected {
+        action: result.action,
+        content: result.forward_content(&message.data),
+        sequence: message.sequence,
+        reason_code: result.reason_code.as_deref(),
+    };
+    let url = format!("{}/publish/{}", bridge_url.trim_end_matches('/'), subject(conversation_id));
+    let (status, _) = outbound.post(&url, "application/json", serde_json::to_vec(&payload)?)?;
+    anyhow::ensure!((200..300).contains(&status), "bridge returned {}", status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::MockOutbound;
+    use crate::test_support::NatsMessageBuilder;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn published(message: &NatsMessage, result: &InspectionResult) -> Vec<(String, serde_json::Value)> {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        let outbound = MockOutbound(Box::new(move |url, body| {
+            recorded.borrow_mut().push((url.to_string(), serde_json::from_slice(body)?));
+            Ok((200, Vec::new()))
+        }));
+        publish(&outbound, "http://bridge:8080/", message, result).unwrap();
+        calls.take()
+    }
+
+    #[test]
+    fn test_inspected_payload_published_to_fanout_subject() {
+        let message = NatsMessageBuilder::new().subject("chat.abc.tokens").data("my password").sequence(7).build();
+        let result = InspectionResult::redact("secret".into(), "my [REDACTED]".into()).with_reason_code("SENSITIVE_KEYWORD");
+        let calls = published(&message, &result);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].0, "http://bridge:8080/publish/chat.abc.inspected");
+        assert_eq!(
+            calls[0].1,
+            serde_json::json!({
+                "action": "redact",
+                "content": "my [REDACTED]",
+                "sequence": 7,
+                "reason_code": "SENSITIVE_KEYWORD",
+            })
+        );
+
+        let result = InspectionResult::drop("injection".into());
+        assert_eq!(published(&message, &result)[0].1["content"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_no_fanout_without_conversation_or_from_fanout_subject() {
+        let result = InspectionResult::allow();
+        assert!(published(&NatsMessageBuilder::new().subject("broadcast").build(), &result).is_empty());
+        assert!(published(&NatsMessageBuilder::new().subject("chat.abc.inspected").build(), &result).is_empty());
+    }
+}
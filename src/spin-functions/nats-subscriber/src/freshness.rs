@@ -0,0 +1,43 @@
+// Freshness checks on the producer's message timestamp (Unix seconds). A
+// timestamp well ahead of our clock means the producer's clock is skewed
+// or the message was tampered with; skew up to the configured tolerance is
+// normal between hosts and allowed.
+
+use std::time::Duration;
+
+use crate::InspectionResult;
+
+pub const FUTURE_TIMESTAMP: &str = "FUTURE_TIMESTAMP";
+
+/// Drop a message timestamped more than `max_future_skew_secs` after `now`.
+/// Messages without a timestamp pass.
+pub fn check(timestamp: Option<i64>, now: Duration, max_future_skew_secs: u64) -> Option<InspectionResult> {
+    let timestamp = timestamp?;
+    let ahead = timestamp.saturating_sub(now.as_secs() as i64);
+    (ahead > max_future_skew_secs as i64).then(|| {
+        InspectionResult::drop(format!(
+            "timestamp is {}s in the future, more than the {}s allowed",
+            ahead, max_future_skew_secs
+        ))
+        .with_reason_code(FUTURE_TIMESTAMP)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: Duration = Duration::from_secs(1_700_000_000);
+
+    #[test]
+    fn test_past_and_missing_timestamps_pass() {
+        assert!(check(Some(1_600_000_000), NOW, 30).is_none());
+        assert!(check(None, NOW, 0).is_none());
+    }
+
+    #[test]
+    fn test_skew_tolerance_is_inclusive() {
+        assert!(check(Some(1_700_000_030), NOW, 30).is_none());
+        assert!(check(Some(1_700_000_031), NOW, 30).is_some());
+    }
+}
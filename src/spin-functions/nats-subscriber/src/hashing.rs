@@ -0,0 +1,113 @@
+// Hash placeholders for redaction. Instead of the fixed `[REDACTED]`, a
+// policy can replace matched text with a truncated digest of it, e.g.
+// `[sha256:9f86d081884c7d65]`, so repeats of the same secret can be
+// correlated in forwarded output without revealing it. The algorithm is
+// named in the placeholder so consumers know how to compare.
+//
+// The digest is keyed with the secret `redaction_hash_key` (HMAC for the
+// SHA families, BLAKE3's keyed mode for BLAKE3). Unkeyed, a low-entropy match
+// such as an SSN could be recovered by hashing every candidate. So a
+// placeholder is only stable within a deployment sharing the key, and
+// changes when the key is rotated.
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sha3::Sha3_256;
+
+/// Hex digits of the digest kept in a placeholder (64 bits).
+pub const PLACEHOLDER_HEX_DIGITS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    #[serde(rename = "sha256")]
+    Sha256,
+    #[serde(rename = "sha3-256")]
+    Sha3_256,
+    #[serde(rename = "blake3")]
+    Blake3,
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "sha3-256" => Ok(HashAlgorithm::Sha3_256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            other => anyhow::bail!(
+                "unknown hash algorithm '{}', expected 'sha256', 'sha3-256' or 'blake3'",
+                other
+            ),
+        }
+    }
+}
+
+impl HashAlgorithm {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Sha3_256 => "sha3-256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    fn digest(self, key: &[u8], data: &[u8]) -> [u8; 32] {
+        match self {
+            HashAlgorithm::Sha256 => Hmac::<Sha256>::new_from_slice(key)
+                .expect("HMAC takes keys of any length")
+                .chain_update(data)
+                .finalize()
+                .into_bytes()
+                .into(),
+            HashAlgorithm::Sha3_256 => Hmac::<Sha3_256>::new_from_slice(key)
+                .expect("HMAC takes keys of any length")
+                .chain_update(data)
+                .finalize()
+                .into_bytes()
+                .into(),
+            HashAlgorithm::Blake3 => {
+                let key = blake3::derive_key("nats-subscriber redaction placeholder", key);
+                *blake3::keyed_hash(&key, data).as_bytes()
+            }
+        }
+    }
+
+    /// The placeholder replacing `text` under `key`, e.g. `[blake3:...]`.
+    pub fn placeholder(self, key: &str, text: &str) -> String {
+        let hex: String = self
+            .digest(key.as_bytes(), text.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        format!("[{}:{}]", self.as_str(), &hex[..PLACEHOLDER_HEX_DIGITS])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_placeholders_name_algorithm_and_are_stable() {
+        // HMAC-SHA256("key", "The quick brown fox jumps over the lazy dog")
+        let fox = "The quick brown fox jumps over the lazy dog";
+        assert_eq!(HashAlgorithm::Sha256.placeholder("key", fox), "[sha256:f7bc83f430538424]");
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Sha3_256, HashAlgorithm::Blake3] {
+            let placeholder = algorithm.placeholder("k3y", "abc");
+            assert!(placeholder.starts_with(&format!("[{}:", algorithm.as_str())));
+            assert_eq!(placeholder.len(), algorithm.as_str().len() + PLACEHOLDER_HEX_DIGITS + 3);
+            assert_eq!(placeholder, algorithm.placeholder("k3y", "abc"));
+            assert_ne!(placeholder, algorithm.placeholder("k3y", "abd"));
+            // Another deployment's key gives another placeholder
+            assert_ne!(placeholder, algorithm.placeholder("other", "abc"));
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("sha3-256".parse::<HashAlgorithm>().unwrap(), HashAlgorithm::Sha3_256);
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
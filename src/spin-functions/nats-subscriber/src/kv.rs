@@ -0,0 +1,122 @@
+// Key/value storage used for state that must outlive a single request
+// (Spin instantiates the component fresh for every delivery).
+
+use anyhow::Result;
+
+/// Minimal key/value interface so handlers can run against Spin KV in
+/// production and an in-memory map in tests.
+pub trait Store {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    fn set(&self, key: &str, value: &[u8]) -> Result<()>;
+    fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// Read a counter stored as a decimal string; missing keys read as zero.
+pub fn read_counter(store: &dyn Store, key: &str) -> Result<u64> {
+    match store.get(key)? {
+        Some(raw) => Ok(std::str::from_utf8(&raw)?.trim().parse()?),
+        None => Ok(0),
+    }
+}
+
+/// Add `delta` to a counter, saturating at zero, and return the new value.
+/// Counters that reach zero are deleted to keep the store tidy.
+///
+/// This is a read-modify-write, not an atomic increment.
+pub fn add_counter(store: &dyn Store, key: &str, delta: i64) -> Result<u64> {
+    let value = read_counter(store, key)?.saturating_add_signed(delta);
+    if value == 0 {
+        store.delete(key)?;
+    } else {
+        store.set(key, value.to_string().as_bytes())?;
+    }
+    Ok(value)
+}
+
+/// The Spin `default` key/value store.
+///
+/// Opening the store can fail when the component has no store configured;
+/// in that case every operation reports the original error.
+pub struct SpinStore(Result<spin_sdk::key_value::Store, String>);
+
+impl SpinStore {
+    pub fn open_default() -> Self {
+        SpinStore(spin_sdk::key_value::Store::open_default().map_err(|e| e.to_string()))
+    }
+
+    fn store(&self) -> Result<&spin_sdk::key_value::Store> {
+        self.0
+            .as_ref()
+            .map_err(|e| anyhow::anyhow!("key/value store unavailable: {}", e))
+    }
+}
+
+impl Store for SpinStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.store()?.get(key)?)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        Ok(self.store()?.set(key, value)?)
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        Ok(self.store()?.delete(key)?)
+    }
+}
+
+/// In-memory store for tests.
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryStore(std::cell::RefCell<std::collections::HashMap<String, Vec<u8>>>);
+
+#[cfg(test)]
+impl Store for MemoryStore {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.0.borrow().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.0.borrow_mut().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.0.borrow_mut().remove(key);
+        Ok(())
+    }
+}
+
+/// Store that is never reachable, for testing KV outages.
+#[cfg(test)]
+pub struct UnavailableStore;
+
+#[cfg(test)]
+impl Store for UnavailableStore {
+    fn get(&self, _key: &str) -> Result<Option<Vec<u8>>> {
+        anyhow::bail!("key/value store unavailable")
+    }
+
+    fn set(&self, _key: &str, _value: &[u8]) -> Result<()> {
+        anyhow::bail!("key/value store unavailable")
+    }
+
+    fn delete(&self, _key: &str) -> Result<()> {
+        anyhow::bail!("key/value store unavailable")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counter_round_trip() {
+        let store = MemoryStore::default();
+        assert_eq!(read_counter(&store, "n").unwrap(), 0);
+        assert_eq!(add_counter(&store, "n", 3).unwrap(), 3);
+        assert_eq!(add_counter(&store, "n", -1).unwrap(), 2);
+        assert_eq!(add_counter(&store, "n", -5).unwrap(), 0);
+        assert!(store.get("n").unwrap().is_none());
+    }
+}
@@ -0,0 +1,49 @@
+// Line-ending normalization for matching. Detectors with multi-line
+// patterns expect `\n`, so `\r\n` and lone `\r` are rewritten before the
+// detectors run, and the spans they report are mapped back onto the
+// original text so the forwarded content keeps its own line endings.
+
+/// `content` with CRLF and CR line endings turned into LF, and for each
+/// byte offset into the result (through its end) the corresponding offset
+/// into `content`. `None` when there is nothing to normalize.
+pub fn normalize(content: &str) -> Option<(String, Vec<usize>)> {
+    if !content.contains('\r') {
+        return None;
+    }
+    let mut normalized = String::with_capacity(content.len());
+    let mut offsets = Vec::with_capacity(content.len() + 1);
+    let mut chars = content.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '\r' {
+            chars.next_if(|&(_, next)| next == '\n');
+            normalized.push('\n');
+            offsets.push(i);
+            continue;
+        }
+        normalized.push(c);
+        offsets.extend(std::iter::repeat_n(i, c.len_utf8()));
+    }
+    offsets.push(content.len());
+    Some((normalized, offsets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lf_content_left_alone() {
+        assert_eq!(normalize("one\ntwo\n"), None);
+    }
+
+    #[test]
+    fn test_offsets_map_back_to_original() {
+        let content = "é\r\nb\rc";
+        let (normalized, offsets) = normalize(content).unwrap();
+        assert_eq!(normalized, "é\nb\nc");
+        // "b" starts after the two-byte CRLF in the original
+        let b = normalized.find('b').unwrap();
+        assert_eq!(&content[offsets[b]..offsets[b + 1]], "b");
+        assert_eq!(&content[offsets[0]..offsets[normalized.len()]], content);
+    }
+}
@@ -0,0 +1,299 @@
+// Operational metrics, rendered in the Prometheus text format by the
+// `/inspect/metrics` endpoint. Values are kept in KV so they survive the
+// per-request component instances that update them. Updates made while a
+// request is handled are collected in `Updates` and written with one
+// read-modify-write at the end, rather than one per message.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::kv::Store;
+use crate::Action;
+
+/// KV key holding the serialized metrics.
+const KEY: &str = "metrics";
+
+/// Upper bounds, in bytes, of the content length histogram buckets. Tokens
+/// are mostly a few bytes; the larger buckets catch batched or
+/// cumulative content.
+pub const CONTENT_LENGTH_BUCKETS: &[u64] = &[4, 16, 64, 256, 1024, 4096, 16384];
+
+/// Category recorded for verdicts without one, i.e. allows.
+pub const NO_CATEGORY: &str = "none";
+
+/// Observations in the `CONTENT_LENGTH_BUCKETS`, counted per bucket (not
+/// cumulatively) with a final overflow bucket.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Histogram {
+    pub buckets: Vec<u64>,
+    pub sum: u64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: u64) {
+        self.buckets.resize(CONTENT_LENGTH_BUCKETS.len() + 1, 0);
+        let bucket = CONTENT_LENGTH_BUCKETS
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or(CONTENT_LENGTH_BUCKETS.len());
+        self.buckets[bucket] += 1;
+        self.sum += value;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        self.buckets.resize(self.buckets.len().max(other.buckets.len()), 0);
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+        self.sum += other.sum;
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metrics {
+    /// Tokens currently held in each conversation's reassembly buffer.
+    /// Conversations with an empty buffer are omitted.
+    pub buffer_depth: BTreeMap<String, u64>,
+    /// Gaps skipped because a missing token never arrived in time.
+    pub gap_timeouts: u64,
+    /// Length of inspected content by verdict category, then action.
+    pub content_length: BTreeMap<String, BTreeMap<String, Histogram>>,
+    /// Average over the latency SLO window; see `slo`.
+    pub inspection_latency_avg_ms: u64,
+    /// Whether the latency SLO is breached and low-risk subjects are being
+    /// shed onto the fast path.
+    pub slo_shedding: bool,
+    /// Messages inspected on the fast path while shedding.
+    pub slo_fast_path_total: u64,
+}
+
+impl Metrics {
+    /// Load the stored metrics; a missing entry reads as all zeroes.
+    pub fn load(store: &dyn Store) -> Result<Self> {
+        match store.get(KEY)? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Metrics::default()),
+        }
+    }
+
+    pub fn save(&self, store: &dyn Store) -> Result<()> {
+        store.set(KEY, &serde_json::to_vec(self)?)
+    }
+
+    pub fn set_buffer_depth(&mut self, conversation_id: &str, depth: usize) {
+        if depth == 0 {
+            self.buffer_depth.remove(conversation_id);
+        } else {
+            self.buffer_depth.insert(conversation_id.to_string(), depth as u64);
+        }
+    }
+
+    /// Record the length of content that got `action`, under `category`.
+    pub fn observe_content_length(&mut self, category: Option<&str>, action: Action, length: usize) {
+        content_length_histogram(&mut self.content_length, category.unwrap_or(NO_CATEGORY), action.as_str())
+            .observe(length as u64);
+    }
+
+    /// Prometheus text exposition of every metric.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP reassembly_buffer_depth Tokens held waiting for an earlier sequence number.\n");
+        out.push_str("# TYPE reassembly_buffer_depth gauge\n");
+        for (conversation_id, depth) in &self.buffer_depth {
+            let _ = writeln!(
+                out,
+                "reassembly_buffer_depth{{conversation=\"{}\"}} {}",
+                escape_label(conversation_id),
+                depth
+            );
+        }
+        out.push_str("# HELP reassembly_gap_timeouts_total Gaps skipped after the gap timeout expired.\n");
+        out.push_str("# TYPE reassembly_gap_timeouts_total counter\n");
+        let _ = writeln!(out, "reassembly_gap_timeouts_total {}", self.gap_timeouts);
+        out.push_str("# HELP inspected_content_length_bytes Length of inspected content by verdict category and action.\n");
+        out.push_str("# TYPE inspected_content_length_bytes histogram\n");
+        for (category, actions) in &self.content_length {
+            for (action, histogram) in actions {
+                let labels = format!("category=\"{}\",action=\"{}\"", escape_label(category), escape_label(action));
+                let mut cumulative = 0;
+                let bounds = CONTENT_LENGTH_BUCKETS.iter().map(|bound| bound.to_string()).chain(["+Inf".to_string()]);
+                for (i, bound) in bounds.enumerate() {
+                    cumulative += histogram.buckets.get(i).copied().unwrap_or(0);
+                    let _ = writeln!(out, "inspected_content_length_bytes_bucket{{{},le=\"{}\"}} {}", labels, bound, cumulative);
+                }
+                let _ = writeln!(out, "inspected_content_length_bytes_sum{{{}}} {}", labels, histogram.sum);
+                let _ = writeln!(out, "inspected_content_length_bytes_count{{{}}} {}", labels, histogram.count());
+            }
+        }
+        out.push_str("# HELP inspection_latency_avg_ms Average inspection latency over the SLO window.\n");
+        out.push_str("# TYPE inspection_latency_avg_ms gauge\n");
+        let _ = writeln!(out, "inspection_latency_avg_ms {}", self.inspection_latency_avg_ms);
+        out.push_str("# HELP slo_shedding Whether low-risk subjects are shed onto the fast path (1) or fully inspected (0).\n");
+        out.push_str("# TYPE slo_shedding gauge\n");
+        let _ = writeln!(out, "slo_shedding {}", u8::from(self.slo_shedding));
+        out.push_str("# HELP slo_fast_path_total Messages inspected with reduced detectors while shedding.\n");
+        out.push_str("# TYPE slo_fast_path_total counter\n");
+        let _ = writeln!(out, "slo_fast_path_total {}", self.slo_fast_path_total);
+        out
+    }
+}
+
+type ContentLengths = BTreeMap<String, BTreeMap<String, Histogram>>;
+
+fn content_length_histogram<'a>(lengths: &'a mut ContentLengths, category: &str, action: &str) -> &'a mut Histogram {
+    lengths.entry(category.to_string()).or_default().entry(action.to_string()).or_default()
+}
+
+/// Metric updates collected over one request, not yet written to KV.
+#[derive(Debug, Default)]
+pub struct Updates {
+    content_length: ContentLengths,
+    /// Buffer depth each stream was left at.
+    buffer_depth: BTreeMap<String, usize>,
+    gap_timeouts: u64,
+    /// Latest SLO window average and shedding state, if anything was timed.
+    slo: Option<(u64, bool)>,
+    slo_fast_path_total: u64,
+}
+
+impl Updates {
+    pub fn observe_content_length(&mut self, category: Option<&str>, action: Action, length: usize) {
+        content_length_histogram(&mut self.content_length, category.unwrap_or(NO_CATEGORY), action.as_str())
+            .observe(length as u64);
+    }
+
+    /// Record the SLO window's state after a timed inspection.
+    pub fn observe_slo(&mut self, latency_avg_ms: u64, shedding: bool, fast_path: bool) {
+        self.slo = Some((latency_avg_ms, shedding));
+        self.slo_fast_path_total += u64::from(fast_path);
+    }
+
+    /// Take the reassembly metrics one conversation's stream collected in
+    /// `stream`: its buffer depth, and the gaps it timed out on.
+    pub fn merge_reassembly(&mut self, conversation_id: &str, stream: &Metrics) {
+        let depth = stream.buffer_depth.get(conversation_id).copied().unwrap_or(0);
+        self.buffer_depth.insert(conversation_id.to_string(), depth as usize);
+        self.gap_timeouts += stream.gap_timeouts;
+    }
+
+    /// Apply the updates to the stored metrics and clear them. Nothing is
+    /// read or written when there are none.
+    pub fn flush(&mut self, store: &dyn Store) -> Result<()> {
+        let updates = std::mem::take(self);
+        if updates.content_length.is_empty()
+            && updates.buffer_depth.is_empty()
+            && updates.gap_timeouts == 0
+            && updates.slo.is_none()
+        {
+            return Ok(());
+        }
+        let mut metrics = Metrics::load(store)?;
+        for (category, actions) in &updates.content_length {
+            for (action, histogram) in actions {
+                content_length_histogram(&mut metrics.content_length, category, action).merge(histogram);
+            }
+        }
+        for (conversation_id, depth) in &updates.buffer_depth {
+            metrics.set_buffer_depth(conversation_id, *depth);
+        }
+        metrics.gap_timeouts += updates.gap_timeouts;
+        if let Some((latency_avg_ms, shedding)) = updates.slo {
+            metrics.inspection_latency_avg_ms = latency_avg_ms;
+            metrics.slo_shedding = shedding;
+        }
+        metrics.slo_fast_path_total += updates.slo_fast_path_total;
+        metrics.save(store)
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_render() {
+        let mut metrics = Metrics { gap_timeouts: 3, ..Metrics::default() };
+        metrics.set_buffer_depth("abc", 2);
+        let out = metrics.render();
+        assert!(out.contains("reassembly_buffer_depth{conversation=\"abc\"} 2\n"));
+        assert!(out.contains("reassembly_gap_timeouts_total 3\n"));
+
+        metrics.set_buffer_depth("abc", 0);
+        assert!(!metrics.render().contains("conversation=\"abc\""));
+    }
+
+    #[test]
+    fn test_content_length_histogram_buckets() {
+        let mut metrics = Metrics::default();
+        for length in [3, 4, 5, 100_000] {
+            metrics.observe_content_length(Some("injection"), Action::Drop, length);
+        }
+        metrics.observe_content_length(None, Action::Allow, 10);
+
+        let histogram = &metrics.content_length["injection"]["drop"];
+        assert_eq!(histogram.buckets, [2, 1, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.sum, 100_012);
+
+        let out = metrics.render();
+        let labels = "category=\"injection\",action=\"drop\"";
+        assert!(out.contains(&format!("inspected_content_length_bytes_bucket{{{},le=\"4\"}} 2\n", labels)));
+        assert!(out.contains(&format!("inspected_content_length_bytes_bucket{{{},le=\"16\"}} 3\n", labels)));
+        assert!(out.contains(&format!("inspected_content_length_bytes_bucket{{{},le=\"16384\"}} 3\n", labels)));
+        assert!(out.contains(&format!("inspected_content_length_bytes_bucket{{{},le=\"+Inf\"}} 4\n", labels)));
+        assert!(out.contains(&format!("inspected_content_length_bytes_count{{{}}} 4\n", labels)));
+        assert!(out.contains("inspected_content_length_bytes_bucket{category=\"none\",action=\"allow\",le=\"16\"} 1\n"));
+    }
+
+    #[test]
+    fn test_updates_written_together() {
+        let store = MemoryStore::default();
+        let mut stored = Metrics { slo_fast_path_total: 2, ..Metrics::default() };
+        stored.observe_content_length(None, Action::Allow, 10);
+        stored.save(&store).unwrap();
+
+        let mut updates = Updates::default();
+        updates.observe_content_length(None, Action::Allow, 20);
+        updates.observe_content_length(Some("secret"), Action::Redact, 5);
+        updates.observe_slo(30, true, true);
+        updates.observe_slo(40, false, false);
+        updates.flush(&store).unwrap();
+
+        let metrics = Metrics::load(&store).unwrap();
+        assert_eq!(metrics.content_length["none"]["allow"].count(), 2);
+        assert_eq!(metrics.content_length["none"]["allow"].sum, 30);
+        assert_eq!(metrics.content_length["secret"]["redact"].count(), 1);
+        assert_eq!(metrics.inspection_latency_avg_ms, 40);
+        assert!(!metrics.slo_shedding);
+        assert_eq!(metrics.slo_fast_path_total, 3);
+
+        // Flushed updates aren't applied twice
+        updates.flush(&store).unwrap();
+        assert_eq!(Metrics::load(&store).unwrap(), metrics);
+    }
+
+    #[test]
+    fn test_round_trip_through_store() {
+        let store = MemoryStore::default();
+        assert_eq!(Metrics::load(&store).unwrap(), Metrics::default());
+
+        let mut metrics = Metrics::default();
+        metrics.set_buffer_depth("abc", 4);
+        metrics.gap_timeouts = 1;
+        metrics.save(&store).unwrap();
+        assert_eq!(Metrics::load(&store).unwrap(), metrics);
+    }
+}
@@ -0,0 +1,50 @@
+// Outbound HTTP used by optional integrations (e.g. translation). Behind a
+// trait so handlers can be tested without a Spin host.
+
+use anyhow::Result;
+use spin_sdk::http::{Method, Request, Response};
+
+pub trait Outbound {
+    /// POST `body` to `url`, returning the response status and body.
+    fn post(&self, url: &str, content_type: &str, body: Vec<u8>) -> Result<(u16, Vec<u8>)>;
+}
+
+/// Outbound HTTP through the Spin host. The target must be listed in the
+/// component's `allowed_outbound_hosts`.
+pub struct SpinOutbound;
+
+impl Outbound for SpinOutbound {
+    fn post(&self, url: &str, content_type: &str, body: Vec<u8>) -> Result<(u16, Vec<u8>)> {
+        let request = Request::builder()
+            .method(Method::Post)
+            .uri(url)
+            .header("content-type", content_type)
+            .body(body)
+            .build();
+        let response: Response = spin_sdk::http::run(spin_sdk::http::send(request))?;
+        Ok((*response.status(), response.into_body()))
+    }
+}
+
+/// Test double answering every request with a canned handler, keyed on the
+/// URL and request body.
+#[cfg(test)]
+pub struct MockOutbound(pub Box<MockHandler>);
+
+#[cfg(test)]
+pub type MockHandler = dyn Fn(&str, &[u8]) -> Result<(u16, Vec<u8>)>;
+
+#[cfg(test)]
+impl MockOutbound {
+    /// An endpoint that is never expected to be called.
+    pub fn unreachable() -> Self {
+        MockOutbound(Box::new(|url, _| anyhow::bail!("unexpected request to {}", url)))
+    }
+}
+
+#[cfg(test)]
+impl Outbound for MockOutbound {
+    fn post(&self, url: &str, _content_type: &str, body: Vec<u8>) -> Result<(u16, Vec<u8>)> {
+        (self.0)(url, &body)
+    }
+}
@@ -0,0 +1,431 @@
+// Inspection policies: the pattern sets `inspect_message` evaluates.
+// A deployment has one default policy plus any number of named policies,
+// selected per request so one gateway can serve several apps.
+
+use anyhow::Context;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::canary;
+use crate::config::Settings;
+use crate::hashing::HashAlgorithm;
+use crate::kv::Store;
+use crate::subject;
+
+/// Request header naming the policy to apply.
+pub const POLICY_HEADER: &str = "x-policy-name";
+
+/// Whether content is allowed unless flagged, or denied unless allowlisted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Posture {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl std::str::FromStr for Posture {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "allow" => Ok(Posture::Allow),
+            "deny" => Ok(Posture::Deny),
+            other => anyhow::bail!("unknown posture '{}', expected 'allow' or 'deny'", other),
+        }
+    }
+}
+
+/// How script-injection markup in output is handled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum XssProtection {
+    #[default]
+    Off,
+    Redact,
+    Drop,
+}
+
+impl std::str::FromStr for XssProtection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "off" => Ok(XssProtection::Off),
+            "redact" => Ok(XssProtection::Redact),
+            "drop" => Ok(XssProtection::Drop),
+            other => anyhow::bail!("unknown xss_protection '{}', expected 'off', 'redact' or 'drop'", other),
+        }
+    }
+}
+
+/// Regular expressions from a policy, compiled once when it's loaded so an
+/// invalid one fails the load rather than being skipped on every message.
+/// Serializes as the patterns as written.
+#[derive(Debug, Clone, Default)]
+pub struct Patterns {
+    patterns: Vec<String>,
+    compiled: Vec<Regex>,
+}
+
+impl Patterns {
+    /// Patterns matching anywhere in the text, ignoring case.
+    pub fn case_insensitive(patterns: Vec<String>) -> anyhow::Result<Self> {
+        Self::compile(patterns, |pattern| format!("(?i){}", pattern))
+    }
+
+    /// Patterns that must match the whole text.
+    pub fn anchored(patterns: Vec<String>) -> anyhow::Result<Self> {
+        Self::compile(patterns, |pattern| format!("^(?:{})$", pattern))
+    }
+
+    fn compile(patterns: Vec<String>, wrap: fn(&str) -> String) -> anyhow::Result<Self> {
+        let compiled = patterns
+            .iter()
+            .map(|pattern| Regex::new(&wrap(pattern)).with_context(|| format!("invalid pattern '{}'", pattern)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Patterns { patterns, compiled })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// The first pattern matching `text`, as written.
+    pub fn find(&self, text: &str) -> Option<&str> {
+        self.compiled
+            .iter()
+            .position(|re| re.is_match(text))
+            .map(|i| self.patterns[i].as_str())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        self.compiled.iter().any(|re| re.is_match(text))
+    }
+}
+
+impl PartialEq for Patterns {
+    fn eq(&self, other: &Self) -> bool {
+        self.patterns == other.patterns
+    }
+}
+
+impl Serialize for Patterns {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.patterns.serialize(serializer)
+    }
+}
+
+fn case_insensitive_patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Patterns, D::Error> {
+    Patterns::case_insensitive(Vec::deserialize(deserializer)?).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+}
+
+fn anchored_patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Patterns, D::Error> {
+    Patterns::anchored(Vec::deserialize(deserializer)?).map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Policy {
+    /// Case-insensitive substrings that cause the message to be redacted.
+    pub sensitive_patterns: Vec<String>,
+    /// Case-insensitive substrings that cause the message to be dropped.
+    pub injection_patterns: Vec<String>,
+    /// Only flag a dotted token as a JWT when its header decodes to JSON with
+    /// an `alg` field. Disabling this matches on shape alone.
+    pub jwt_validate_header: bool,
+    /// `deny` drops any message that doesn't match one of `allow_patterns`.
+    pub posture: Posture,
+    /// Regular expressions that must match a message in full for it to pass
+    /// under deny posture, e.g. the expected token vocabulary.
+    #[serde(deserialize_with = "anchored_patterns")]
+    pub allow_patterns: Patterns,
+    /// Regular expressions for content known to be safe: a message matching
+    /// one in full is allowed even if a detector would redact it, though a
+    /// drop still wins. Any posture.
+    #[serde(deserialize_with = "anchored_patterns")]
+    pub allow_override_patterns: Patterns,
+    /// Output sanitization for `<script>`, `javascript:` and `on*=` markup.
+    pub xss_protection: XssProtection,
+    /// Case-insensitive regular expressions for URLs whose markdown links
+    /// are redacted, e.g. known phishing hosts.
+    #[serde(deserialize_with = "case_insensitive_patterns")]
+    pub suspicious_link_patterns: Patterns,
+    /// More findings than this drops the message outright instead of
+    /// redacting each one; a message with that many hits is a data dump.
+    pub max_matches: Option<usize>,
+    /// Longest token accepted, in characters rather than bytes; a single
+    /// oversized token is a smuggling or rendering risk and is dropped.
+    pub max_token_chars: Option<usize>,
+    /// Longest run of one repeated character accepted; a longer run is
+    /// flooding and is dropped.
+    pub max_char_run: Option<usize>,
+    /// Inspect the text of attachments (e.g. file snippets) in structured
+    /// chunks, not just the message content.
+    pub inspect_attachments: bool,
+    /// Scrub secrets and home directories from stack traces echoed in
+    /// content.
+    pub scrub_stack_traces: bool,
+    /// Longest `reason` reported, in characters; longer reasons, which can
+    /// quote matched content, are cut short with an ellipsis.
+    pub max_reason_chars: Option<usize>,
+    /// Re-inspect redacted output and drop the message if any detector
+    /// still matches it, rather than forwarding a leaky redaction.
+    pub verify_redaction: bool,
+    /// Redact US Social Security Numbers, keeping the last four digits.
+    pub pii_ssn: bool,
+    /// Redact street addresses found by the heuristic `ner` detector.
+    /// Best-effort, so off by default.
+    pub pii_ner: bool,
+    /// Undo common leetspeak substitutions (`1gn0re` for `ignore`) before
+    /// matching injection patterns. Only the matching input is normalized.
+    pub normalize_leet: bool,
+    /// Findings less confident than this are logged but not acted on.
+    pub min_confidence: Option<f32>,
+    /// Per-detector thresholds, by detector name, overriding
+    /// `min_confidence` for that detector's findings.
+    pub detector_min_confidence: BTreeMap<String, f32>,
+    /// Base64/gzip layers to unwrap before inspecting; zero inspects
+    /// content as it arrives.
+    pub max_decode_depth: usize,
+    /// Replace redacted text with `[{algorithm}:{digest}]` instead of
+    /// `[REDACTED]`, so repeated values can be correlated. The digest is
+    /// keyed with `redaction_hash_key`, and without one `[REDACTED]` is
+    /// used.
+    pub redaction_hash: Option<HashAlgorithm>,
+    /// Key of the `redaction_hash` digest, set from the secret variable
+    /// rather than configured with the policy.
+    #[serde(skip)]
+    pub redaction_hash_key: Option<String>,
+    /// Detector names to run first, in order, e.g. cheap keyword checks
+    /// before regex scans; unnamed detectors run afterwards.
+    pub detector_order: Vec<String>,
+    /// Stop running detectors once one reports a drop. Cheaper, at the cost
+    /// of not reporting the overridden findings in `secondary_actions`.
+    pub short_circuit_on_drop: bool,
+    /// Names of the detectors to run; empty runs them all.
+    pub enabled_detectors: Vec<String>,
+    /// Detectors whose redactions are highlighted instead: the content is
+    /// forwarded unchanged with the matches marked, for the user to confirm.
+    pub highlight_detectors: Vec<String>,
+    /// Canary tokens for the `canary` detector, loaded from KV by `select`
+    /// rather than configured.
+    #[serde(skip)]
+    pub canary_tokens: canary::Tokens,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            sensitive_patterns: ["password", "secret", "api_key", "credit_card"]
+                .map(String::from)
+                .to_vec(),
+            injection_patterns: [
+                "ignore previous",
+                "disregard above",
+                "new instructions",
+                "system prompt",
+            ]
+            .map(String::from)
+            .to_vec(),
+            jwt_validate_header: true,
+            posture: Posture::Allow,
+            allow_patterns: Patterns::default(),
+            allow_override_patterns: Patterns::default(),
+            xss_protection: XssProtection::Off,
+            suspicious_link_patterns: Patterns::default(),
+            max_matches: None,
+            max_token_chars: None,
+            max_char_run: None,
+            inspect_attachments: true,
+            scrub_stack_traces: false,
+            max_reason_chars: None,
+            verify_redaction: false,
+            pii_ssn: true,
+            pii_ner: false,
+            normalize_leet: false,
+            min_confidence: None,
+            detector_min_confidence: BTreeMap::new(),
+            max_decode_depth: 0,
+            redaction_hash: None,
+            redaction_hash_key: None,
+            detector_order: Vec::new(),
+            short_circuit_on_drop: false,
+            enabled_detectors: Vec::new(),
+            highlight_detectors: Vec::new(),
+            canary_tokens: canary::Tokens::Off,
+        }
+    }
+}
+
+impl Policy {
+    /// The confidence a finding from `detector` needs to be acted on.
+    pub fn min_confidence_for(&self, detector: &str) -> f32 {
+        self.detector_min_confidence
+            .get(detector)
+            .copied()
+            .or(self.min_confidence)
+            .unwrap_or(0.0)
+    }
+
+    /// Short hex digest identifying the rules, so a verdict can be traced to
+    /// the exact policy that produced it. Stable given the same fields.
+    pub fn ruleset_hash(&self) -> String {
+        let encoded = serde_json::to_vec(self).expect("policy serializes");
+        Sha256::digest(&encoded)[..8]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+/// KV key under which a named policy may be stored as JSON.
+fn kv_key(name: &str) -> String {
+    format!("policy/{}", name)
+}
+
+/// Store a named policy in KV, replacing any previous version.
+pub fn save(name: &str, policy: &Policy, store: &dyn Store) -> anyhow::Result<()> {
+    store.set(&kv_key(name), &serde_json::to_vec(policy)?)
+}
+
+/// The name of the policy for a message on `subject`: the one the caller
+/// asked for, else that of the first `subject_policies` pattern matching
+/// the subject.
+pub fn name_for<'a>(requested: Option<&'a str>, subject: &str, settings: &'a Settings) -> Option<&'a str> {
+    requested.filter(|name| !name.trim().is_empty()).or_else(|| {
+        settings
+            .subject_policies
+            .iter()
+            .find(|(pattern, _)| subject::matches(pattern, subject))
+            .map(|(_, name)| name.as_str())
+    })
+}
+
+/// Resolve the policy for a request.
+///
+/// Named policies are looked up in the loaded config first, then in KV.
+/// A missing name yields the default policy; an unknown or unreadable one
+/// logs a warning and also falls back to the default rather than failing.
+/// With `canary_tokens` set the stored tokens are loaded into it, and a
+/// policy from KV is given the `redaction_hash_key`.
+pub fn select<'a>(name: Option<&str>, settings: &'a Settings, store: &dyn Store) -> Cow<'a, Policy> {
+    let mut policy = lookup(name, settings, store);
+    if policy.redaction_hash_key != settings.redaction_hash_key {
+        policy.to_mut().redaction_hash_key = settings.redaction_hash_key.clone();
+    }
+    if !settings.canary_tokens {
+        return policy;
+    }
+    let canary_tokens = canary::Tokens::load(store, settings.kv_failure_mode);
+    Cow::Owned(Policy { canary_tokens, ..policy.into_owned() })
+}
+
+fn lookup<'a>(name: Option<&str>, settings: &'a Settings, store: &dyn Store) -> Cow<'a, Policy> {
+    let Some(name) = name.map(str::trim).filter(|n| !n.is_empty()) else {
+        return Cow::Borrowed(&settings.default_policy);
+    };
+
+    if let Some(policy) = settings.policies.get(name) {
+        return Cow::Borrowed(policy);
+    }
+
+    match store.get(&kv_key(name)) {
+        Ok(Some(raw)) => match serde_json::from_slice(&raw) {
+            Ok(policy) => return Cow::Owned(policy),
+            Err(e) => eprintln!("warning: policy '{}' in KV is invalid: {}", name, e),
+        },
+        Ok(None) => eprintln!("warning: unknown policy '{}', using default", name),
+        Err(e) => eprintln!("warning: could not load policy '{}': {}", name, e),
+    }
+
+    Cow::Borrowed(&settings.default_policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    fn settings_with(name: &str, policy: Policy) -> Settings {
+        let mut settings = Settings::default();
+        settings.policies.insert(name.to_string(), policy);
+        settings
+    }
+
+    #[test]
+    fn test_select_default_without_name() {
+        let settings = Settings::default();
+        let policy = select(None, &settings, &MemoryStore::default());
+        assert_eq!(*policy, Policy::default());
+    }
+
+    #[test]
+    fn test_select_from_config() {
+        let strict = Policy {
+            sensitive_patterns: vec!["internal".into()],
+            injection_patterns: vec![],
+            ..Policy::default()
+        };
+        let settings = settings_with("strict", strict.clone());
+        let policy = select(Some("strict"), &settings, &MemoryStore::default());
+        assert_eq!(*policy, strict);
+    }
+
+    #[test]
+    fn test_subject_mapped_to_policy() {
+        let settings = Settings {
+            subject_policies: vec![
+                ("support.*".into(), "strict".into()),
+                ("marketing.>".into(), "lax".into()),
+                ("support.vip".into(), "unreachable".into()),
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(name_for(None, "support.abc", &settings), Some("strict"));
+        assert_eq!(name_for(None, "support.vip", &settings), Some("strict"));
+        assert_eq!(name_for(None, "marketing.abc.tokens", &settings), Some("lax"));
+        // The caller's choice wins, and unmapped subjects get the default
+        assert_eq!(name_for(Some("custom"), "support.abc", &settings), Some("custom"));
+        assert_eq!(name_for(Some(" "), "support.abc", &settings), Some("strict"));
+        assert_eq!(name_for(None, "chat.abc.tokens", &settings), None);
+    }
+
+    #[test]
+    fn test_select_from_kv() {
+        let store = MemoryStore::default();
+        store
+            .set("policy/lax", br#"{"injection_patterns": []}"#)
+            .unwrap();
+        let settings = Settings::default();
+        let policy = select(Some("lax"), &settings, &store);
+        assert!(policy.injection_patterns.is_empty());
+        assert_eq!(policy.sensitive_patterns, Policy::default().sensitive_patterns);
+    }
+
+    #[test]
+    fn test_select_unknown_falls_back() {
+        let settings = Settings::default();
+        let policy = select(Some("missing"), &settings, &MemoryStore::default());
+        assert_eq!(*policy, Policy::default());
+    }
+
+    #[test]
+    fn test_patterns_compiled_on_load() {
+        let policy: Policy = serde_json::from_str(r#"{"suspicious_link_patterns": ["evil\\.example"]}"#).unwrap();
+        assert_eq!(policy.suspicious_link_patterns.find("http://EVIL.example/x"), Some(r"evil\.example"));
+        assert_eq!(serde_json::to_value(&policy).unwrap()["suspicious_link_patterns"][0], r"evil\.example");
+
+        let invalid = serde_json::from_str::<Policy>(r#"{"suspicious_link_patterns": ["evil(["]}"#).unwrap_err();
+        assert!(invalid.to_string().contains("invalid pattern 'evil(['"));
+
+        // Allow patterns must match in full
+        let policy: Policy = serde_json::from_str(r#"{"allow_patterns": ["[a-z]+"]}"#).unwrap();
+        assert!(policy.allow_patterns.is_match("hello"));
+        assert!(!policy.allow_patterns.is_match("hello world"));
+        assert!(serde_json::from_str::<Policy>(r#"{"allow_override_patterns": ["(unclosed"]}"#).is_err());
+    }
+}
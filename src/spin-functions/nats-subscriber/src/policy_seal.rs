@@ -0,0 +1,74 @@
+// Per-conversation policy seal. With `seal_policy` set, the policy in effect
+// when a conversation's first token is inspected is pinned in KV, and every
+// later token of the conversation is inspected under that copy, so a reload
+// or config change mid-stream can't make one answer inconsistent. The seal
+// is released when the stream closes, or, for tokens inspected one message
+// at a time, when the conversation's `done` or `cancel` message arrives.
+
+use std::borrow::Cow;
+
+use anyhow::{Context, Result};
+
+use crate::kv::Store;
+use crate::policy::Policy;
+use crate::subject::{self, Control};
+
+fn key(conversation_id: &str) -> String {
+    format!("policy_seal/{}", conversation_id)
+}
+
+/// The policy sealed for the conversation, sealing `current` if the
+/// conversation has none yet.
+pub fn pin<'a>(store: &dyn Store, conversation_id: &str, current: &'a Policy) -> Result<Cow<'a, Policy>> {
+    let key = key(conversation_id);
+    if let Some(raw) = store.get(&key)? {
+        let sealed: Policy = serde_json::from_slice(&raw).context("sealed policy is invalid")?;
+        // Canary tokens and the hash key aren't sealed; the current ones
+        // always apply
+        let canary_tokens = current.canary_tokens.clone();
+        let redaction_hash_key = current.redaction_hash_key.clone();
+        return Ok(Cow::Owned(Policy { canary_tokens, redaction_hash_key, ..sealed }));
+    }
+    store.set(&key, &serde_json::to_vec(current)?)?;
+    Ok(Cow::Borrowed(current))
+}
+
+/// Drop the conversation's seal once its stream has closed.
+pub fn release(store: &dyn Store, conversation_id: &str) -> Result<()> {
+    store.delete(&key(conversation_id))
+}
+
+/// Release the seal of the conversation a `chat.{id}.done` or
+/// `chat.{id}.cancel` `subject` closes, if it is one.
+pub fn release_if_closing(store: &dyn Store, subject: &str) -> Result<()> {
+    match subject::control(subject) {
+        Some((conversation_id, Control::Done | Control::Cancel)) => release(store, conversation_id),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_first_policy_pinned_until_released() {
+        let store = MemoryStore::default();
+        let first = Policy::default();
+        let changed = Policy { pii_ssn: false, ..Policy::default() };
+        assert_eq!(*pin(&store, "abc", &first).unwrap(), first);
+        assert_eq!(*pin(&store, "abc", &changed).unwrap(), first);
+
+        // Other conversations seal whatever is current
+        assert_eq!(*pin(&store, "xyz", &changed).unwrap(), changed);
+
+        release(&store, "abc").unwrap();
+        assert_eq!(*pin(&store, "abc", &changed).unwrap(), changed);
+
+        release_if_closing(&store, "chat.xyz.tokens").unwrap();
+        assert_eq!(*pin(&store, "xyz", &first).unwrap(), changed);
+        release_if_closing(&store, "chat.xyz.done").unwrap();
+        assert_eq!(*pin(&store, "xyz", &first).unwrap(), first);
+    }
+}
@@ -0,0 +1,216 @@
+// Protobuf payloads. NATS messages reach us as JSON, so the encoded bytes
+// arrive base64 in `data`. With a `messageType` hint naming one of the
+// known shapes below, the message is decoded, its text fields are
+// inspected, and redactions are re-encoded in place. Without a known hint
+// the raw bytes are scanned for printable strings instead; a secret found
+// that way can't be redacted without breaking the framing, so it drops.
+//
+// Only the wire format is decoded, top-level fields only, so no generated
+// code or descriptors are needed.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::policy::Policy;
+use crate::{inspect_message, Action, InspectionResult};
+
+pub const PROTOBUF: &str = "application/x-protobuf";
+
+/// Known message types and the field numbers of their text fields, which
+/// are the ones inspected.
+const SCHEMAS: &[(&str, &[u32])] = &[
+    // message TokenChunk { string conversation_id = 1; uint64 sequence = 2; string content = 3; }
+    ("llm.TokenChunk", &[3]),
+    // message ChatMessage { string role = 1; string content = 2; }
+    ("llm.ChatMessage", &[2]),
+];
+
+/// Shortest printable run scanned when there is no schema.
+const MIN_PRINTABLE_RUN: usize = 4;
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+/// One top-level field. `value` is the payload for length-delimited fields
+/// and the raw encoded value otherwise.
+#[derive(Debug, Clone, PartialEq)]
+struct Field {
+    number: u32,
+    wire_type: u8,
+    value: Vec<u8>,
+}
+
+/// Inspect base64 protobuf `data` of the type named by `message_type`.
+pub fn inspect(data: &str, message_type: Option<&str>, policy: &Policy) -> InspectionResult {
+    let Ok(bytes) = STANDARD.decode(data.trim()) else {
+        eprintln!("warning: {} payload isn't base64, inspecting as text", PROTOBUF);
+        return inspect_message(data, policy);
+    };
+    let schema = message_type.and_then(|name| SCHEMAS.iter().find(|(known, _)| *known == name));
+    let fields = schema.and_then(|_| decode(&bytes));
+    match (schema, fields) {
+        (Some((_, text_fields)), Some(fields)) => inspect_fields(fields, text_fields, policy),
+        _ => scan_raw(&bytes, policy),
+    }
+}
+
+fn inspect_fields(mut fields: Vec<Field>, text_fields: &[u32], policy: &Policy) -> InspectionResult {
+    let mut reasons = Vec::new();
+    let mut redacted = false;
+    for field in fields.iter_mut().filter(|f| f.wire_type == WIRE_LEN && text_fields.contains(&f.number)) {
+        let Ok(text) = std::str::from_utf8(&field.value) else {
+            continue;
+        };
+        let result = inspect_message(text, policy);
+        match result.action {
+            Action::Drop => return result,
+            Action::Redact => {
+                field.value = result.redacted_content.unwrap_or_default().into_bytes();
+                reasons.extend(result.reason);
+                redacted = true;
+            }
+            Action::Allow | Action::Highlight => {}
+        }
+    }
+    if !redacted {
+        return InspectionResult::allow();
+    }
+    InspectionResult::redact(reasons.join("; "), STANDARD.encode(encode(&fields)))
+}
+
+/// Inspect each printable run in the raw bytes.
+fn scan_raw(bytes: &[u8], policy: &Policy) -> InspectionResult {
+    for run in bytes.split(|b| !(b' '..=b'~').contains(b)) {
+        if run.len() < MIN_PRINTABLE_RUN {
+            continue;
+        }
+        let text = std::str::from_utf8(run).expect("printable ASCII");
+        let result = inspect_message(text, policy);
+        match result.action {
+            Action::Drop => return result,
+            Action::Redact => {
+                let mut dropped = InspectionResult::drop(format!(
+                    "{} in protobuf without a known schema, which can't be redacted in place",
+                    result.reason.unwrap_or_default()
+                ));
+                dropped.reason_code = result.reason_code;
+                dropped.category = result.category;
+                dropped.secondary_actions = vec![Action::Redact];
+                return dropped;
+            }
+            Action::Allow | Action::Highlight => {}
+        }
+    }
+    InspectionResult::allow()
+}
+
+fn decode(mut bytes: &[u8]) -> Option<Vec<Field>> {
+    let mut fields = Vec::new();
+    while !bytes.is_empty() {
+        let key = read_varint(&mut bytes)?;
+        let number = u32::try_from(key >> 3).ok()?;
+        let wire_type = (key & 7) as u8;
+        let len = match wire_type {
+            WIRE_VARINT => bytes.iter().position(|b| b & 0x80 == 0)? + 1,
+            WIRE_FIXED64 => 8,
+            WIRE_LEN => usize::try_from(read_varint(&mut bytes)?).ok()?,
+            WIRE_FIXED32 => 4,
+            _ => return None,
+        };
+        if len > bytes.len() {
+            return None;
+        }
+        let (value, rest) = bytes.split_at(len);
+        fields.push(Field { number, wire_type, value: value.to_vec() });
+        bytes = rest;
+    }
+    Some(fields)
+}
+
+fn encode(fields: &[Field]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for field in fields {
+        write_varint(&mut out, (u64::from(field.number) << 3) | u64::from(field.wire_type));
+        if field.wire_type == WIRE_LEN {
+            write_varint(&mut out, field.value.len() as u64);
+        }
+        out.extend_from_slice(&field.value);
+    }
+    out
+}
+
+fn read_varint(bytes: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, &byte) in bytes.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *bytes = &bytes[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(value: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, value);
+        out
+    }
+
+    fn token_chunk(conversation_id: &str, sequence: u64, content: &str) -> Vec<u8> {
+        encode(&[
+            Field { number: 1, wire_type: WIRE_LEN, value: conversation_id.as_bytes().to_vec() },
+            Field { number: 2, wire_type: WIRE_VARINT, value: varint(sequence) },
+            Field { number: 3, wire_type: WIRE_LEN, value: content.as_bytes().to_vec() },
+        ])
+    }
+
+    #[test]
+    fn test_secret_in_string_field_redacted_and_reencoded() {
+        let data = STANDARD.encode(token_chunk("abc", 300, "my password is hunter2"));
+        let result = inspect(&data, Some("llm.TokenChunk"), &Policy::default());
+        assert_eq!(result.action, Action::Redact);
+
+        let bytes = STANDARD.decode(result.redacted_content.unwrap()).unwrap();
+        assert_eq!(bytes, token_chunk("abc", 300, "[REDACTED]"));
+    }
+
+    #[test]
+    fn test_only_text_fields_inspected() {
+        // "secret" in the conversation id isn't content
+        let data = STANDARD.encode(token_chunk("secret-convo", 1, "hello"));
+        assert_eq!(inspect(&data, Some("llm.TokenChunk"), &Policy::default()).action, Action::Allow);
+    }
+
+    #[test]
+    fn test_without_schema_raw_strings_scanned() {
+        let data = STANDARD.encode(token_chunk("abc", 1, "my password is hunter2"));
+        let result = inspect(&data, None, &Policy::default());
+        assert_eq!(result.action, Action::Drop);
+        assert_eq!(result.reason_code.as_deref(), Some("SENSITIVE_KEYWORD"));
+
+        let clean = STANDARD.encode(token_chunk("abc", 1, "hello there"));
+        assert_eq!(inspect(&clean, Some("unknown.Type"), &Policy::default()).action, Action::Allow);
+    }
+
+    #[test]
+    fn test_varint_round_trip() {
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(read_varint(&mut varint(value).as_slice()), Some(value));
+        }
+    }
+}
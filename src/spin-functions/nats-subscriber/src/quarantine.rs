@@ -0,0 +1,115 @@
+// Quarantine of redacted originals. Incident response sometimes needs the
+// content a redaction hid, so with `quarantine_bridge_url` set the original
+// of every redacted message is published to `quarantine.{id}` through the
+// HTTP-to-NATS bridge, sealed with AES-256-GCM under the deployment's
+// `quarantine_key`. Only holders of the key can read it back; the live
+// stream still gets the redacted version.
+//
+// The subject the message arrived on is bound in as associated data, so a
+// sealed original can't be passed off as another conversation's.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+use crate::outbound::Outbound;
+use crate::{InspectionResult, NatsMessage};
+
+/// Length of the decoded key, for AES-256.
+const KEY_LEN: usize = 32;
+
+/// Payload published for each quarantined original.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Sealed {
+    /// Subject the original arrived on, which is also the associated data.
+    pub subject: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason_code: Option<String>,
+    /// Base64 96-bit nonce.
+    pub nonce: String,
+    /// Base64 ciphertext and tag.
+    pub ciphertext: String,
+}
+
+pub fn subject(conversation_id: &str) -> String {
+    format!("quarantine.{}", conversation_id)
+}
+
+/// Decode a base64 `quarantine_key`, which must be 32 bytes.
+pub fn parse_key(encoded: &str) -> Result<Key<Aes256Gcm>> {
+    let bytes = STANDARD.decode(encoded.trim()).context("quarantine key isn't base64")?;
+    anyhow::ensure!(bytes.len() == KEY_LEN, "quarantine key must be {} bytes, got {}", KEY_LEN, bytes.len());
+    Ok(*Key::<Aes256Gcm>::from_slice(&bytes))
+}
+
+/// Seal `message`'s original content under `key`.
+pub(crate) fn seal(key: &str, message: &NatsMessage, result: &InspectionResult) -> Result<Sealed> {
+    let cipher = Aes256Gcm::new(&parse_key(key)?);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let payload = Payload { msg: message.data.as_bytes(), aad: message.subject.as_bytes() };
+    let ciphertext = cipher
+        .encrypt(&nonce, payload)
+        .map_err(|_| anyhow::anyhow!("quarantine encryption failed"))?;
+    Ok(Sealed {
+        subject: message.subject.clone(),
+        sequence: message.sequence,
+        reason_code: result.reason_code.clone(),
+        nonce: STANDARD.encode(nonce),
+        ciphertext: STANDARD.encode(ciphertext),
+    })
+}
+
+/// Recover the original from a sealed payload, as a reviewer would.
+pub fn open(key: &str, sealed: &Sealed) -> Result<String> {
+    let cipher = Aes256Gcm::new(&parse_key(key)?);
+    let nonce = STANDARD.decode(&sealed.nonce)?;
+    anyhow::ensure!(nonce.len() == 12, "quarantine nonce must be 12 bytes");
+    let ciphertext = STANDARD.decode(&sealed.ciphertext)?;
+    let payload = Payload { msg: &ciphertext, aad: sealed.subject.as_bytes() };
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), payload)
+        .map_err(|_| anyhow::anyhow!("quarantined content doesn't decrypt with this key"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+/// Publish a sealed original to the conversation's quarantine subject.
+pub fn publish(outbound: &dyn Outbound, bridge_url: &str, conversation_id: &str, sealed: &Sealed) -> Result<()> {
+    let url = format!("{}/publish/{}", bridge_url.trim_end_matches('/'), subject(conversation_id));
+    let (status, _) = outbound.post(&url, "application/json", serde_json::to_vec(sealed)?)?;
+    anyhow::ensure!((200..300).contains(&status), "bridge returned {}", status);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::NatsMessageBuilder;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_sealed_original_opens_only_with_key() {
+        let message = NatsMessageBuilder::new().subject("chat.abc.tokens").data("my password is hunter2").build();
+        let sealed = seal(KEY, &message, &InspectionResult::allow()).unwrap();
+        assert!(!STANDARD.decode(&sealed.ciphertext).unwrap().windows(7).any(|w| w == b"hunter2"));
+        assert_eq!(open(KEY, &sealed).unwrap(), "my password is hunter2");
+
+        let other = STANDARD.encode([7u8; KEY_LEN]);
+        assert!(open(&other, &sealed).is_err());
+        // Bound to the subject it arrived on
+        let moved = Sealed { subject: "chat.xyz.tokens".into(), ..sealed };
+        assert!(open(KEY, &moved).is_err());
+    }
+
+    #[test]
+    fn test_key_must_be_32_bytes() {
+        assert!(parse_key(KEY).is_ok());
+        assert!(parse_key(&STANDARD.encode([0u8; 16])).is_err());
+        assert!(parse_key("not base64!").is_err());
+    }
+}
@@ -0,0 +1,579 @@
+// Sequence reassembly for the SSE stream. NATS delivery across replicas can
+// reorder tokens, so the component serving a conversation holds early
+// tokens in a `TokenBuffer` until the gap before them is filled, or skips
+// the gap once it has been open longer than the gap timeout.
+//
+// A token that turns up after its gap was skipped can still be delivered,
+// out of order, within the late grace window. That trades strict ordering
+// for completeness: consumers that enable it must be prepared to splice a
+// `correction` event into text they have already rendered.
+//
+// A gap wider than the maximum sequence gap isn't a reorder but a bug or a
+// forged sequence number, so it is skipped at once instead of waited on,
+// and a token that far behind the stream position is reported.
+//
+// Producers differ in where sequences start, so the first expected
+// sequence is `sequence_start`. Tokens without a sequence, from channels
+// that aren't ordered, bypass the buffer and are released as they arrive.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::config::Settings;
+use crate::metrics::Metrics;
+
+/// What the buffer hands to the gateway, in stream order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Release {
+    Token { sequence: u64, content: String },
+    /// Sequences `first..=last` never arrived and were skipped.
+    Gap { first: u64, last: u64 },
+    /// A token from a skipped gap that arrived within the late grace
+    /// window. It belongs before tokens already released.
+    Correction { sequence: u64, content: String },
+    /// A token without a sequence, released in arrival order.
+    Unsequenced { content: String },
+}
+
+/// A buffered token and when it arrived.
+#[derive(Debug)]
+struct Pending {
+    content: String,
+    arrived_ms: u64,
+}
+
+/// A gap that was skipped, remembered for the late grace window.
+#[derive(Debug)]
+struct SkippedGap {
+    first: u64,
+    last: u64,
+    skipped_at_ms: u64,
+    /// Sequences already released as corrections, so duplicates are ignored.
+    corrected: BTreeSet<u64>,
+}
+
+/// Per-conversation reorder buffer.
+///
+/// Time is passed in as milliseconds rather than read from a clock, so the
+/// caller decides the time source and tests can step time explicitly.
+#[derive(Debug)]
+pub struct TokenBuffer {
+    conversation_id: String,
+    next: u64,
+    pending: BTreeMap<u64, Pending>,
+    /// When the current gap opened, i.e. the first token was buffered
+    /// behind a missing sequence.
+    gap_since_ms: Option<u64>,
+    gap_timeout_ms: u64,
+    late_grace_ms: u64,
+    skipped: Vec<SkippedGap>,
+    message_ttl_ms: Option<u64>,
+    max_sequence_gap: Option<u64>,
+    last_activity_ms: u64,
+}
+
+impl TokenBuffer {
+    /// A buffer expecting `first_sequence` next.
+    pub fn new(conversation_id: impl Into<String>, first_sequence: u64, gap_timeout_ms: u64) -> Self {
+        TokenBuffer {
+            conversation_id: conversation_id.into(),
+            next: first_sequence,
+            pending: BTreeMap::new(),
+            gap_since_ms: None,
+            gap_timeout_ms,
+            late_grace_ms: 0,
+            skipped: Vec::new(),
+            message_ttl_ms: None,
+            max_sequence_gap: None,
+            last_activity_ms: 0,
+        }
+    }
+
+    /// A buffer expecting the configured `sequence_start` next.
+    pub fn from_settings(conversation_id: impl Into<String>, settings: &Settings) -> Self {
+        TokenBuffer::new(conversation_id, settings.sequence_start, settings.gap_timeout_ms)
+            .with_late_grace_ms(settings.late_grace_ms)
+            .with_message_ttl_ms(settings.message_ttl_ms)
+            .with_max_sequence_gap(settings.max_sequence_gap)
+    }
+
+    /// Expect `first_sequence` next instead, e.g. when resuming a stream
+    /// part way through.
+    pub fn with_first_sequence(mut self, first_sequence: u64) -> Self {
+        self.next = first_sequence;
+        self
+    }
+
+    /// Accept tokens up to `late_grace_ms` after their gap was skipped, as
+    /// corrections. Zero (the default) discards them.
+    pub fn with_late_grace_ms(mut self, late_grace_ms: u64) -> Self {
+        self.late_grace_ms = late_grace_ms;
+        self
+    }
+
+    /// Evict buffered tokens older than `message_ttl_ms` in `gc`, and treat
+    /// the buffer as stale once it has been empty and silent that long.
+    pub fn with_message_ttl_ms(mut self, message_ttl_ms: Option<u64>) -> Self {
+        self.message_ttl_ms = message_ttl_ms;
+        self
+    }
+
+    /// Skip a gap of more than `max_sequence_gap` missing tokens as soon as
+    /// the token after it arrives, rather than waiting out the gap timeout.
+    pub fn with_max_sequence_gap(mut self, max_sequence_gap: Option<u64>) -> Self {
+        self.max_sequence_gap = max_sequence_gap;
+        self
+    }
+
+    /// Tokens held waiting for an earlier sequence.
+    pub fn depth(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Bytes of content held in the buffer.
+    pub fn bytes(&self) -> usize {
+        self.pending.values().map(|pending| pending.content.len()).sum()
+    }
+
+    /// Release everything buffered now, skipping every open gap, e.g. when
+    /// the buffer is about to be discarded.
+    pub fn drain(&mut self, metrics: &mut Metrics) -> Vec<Release> {
+        let mut released = Vec::new();
+        while let Some(&first_pending) = self.pending.keys().next() {
+            if first_pending > self.next {
+                released.push(Release::Gap { first: self.next, last: first_pending - 1 });
+                self.next = first_pending;
+            }
+            released.extend(self.drain_ready());
+        }
+        self.gap_since_ms = None;
+        metrics.set_buffer_depth(&self.conversation_id, 0);
+        released
+    }
+
+    /// Discard everything buffered without releasing it, e.g. when the
+    /// conversation is cancelled.
+    pub fn purge(&mut self, metrics: &mut Metrics) {
+        self.pending.clear();
+        self.skipped.clear();
+        self.gap_since_ms = None;
+        metrics.set_buffer_depth(&self.conversation_id, 0);
+    }
+
+    /// Accept a token and return everything now ready to emit.
+    ///
+    /// Duplicates are discarded, as are tokens older than the stream
+    /// position unless they fill a gap skipped within the late grace window.
+    pub fn push(&mut self, sequence: u64, content: String, now_ms: u64, metrics: &mut Metrics) -> Vec<Release> {
+        self.last_activity_ms = now_ms;
+        if sequence >= self.next {
+            self.pending
+                .entry(sequence)
+                .or_insert(Pending { content, arrived_ms: now_ms });
+            let mut released = Vec::new();
+            if self.exceeds_max_gap(sequence - self.next) {
+                eprintln!(
+                    "warning: sequence jumped from {} to {} for conversation {}, skipping the gap",
+                    self.next, sequence, self.conversation_id
+                );
+                while self.next <= sequence {
+                    let first_pending = *self.pending.keys().next().expect("sequence is pending");
+                    if first_pending > self.next {
+                        released.push(self.skip_to(first_pending, now_ms));
+                    }
+                    released.extend(self.drain_ready());
+                }
+            }
+            released.extend(self.poll(now_ms, metrics));
+            return released;
+        }
+
+        self.expire_skipped(now_ms);
+        // Some(false) means this token was already sent as a correction
+        let first_correction = self
+            .skipped
+            .iter_mut()
+            .find(|gap| (gap.first..=gap.last).contains(&sequence))
+            .map(|gap| gap.corrected.insert(sequence));
+        let mut released = self.poll(now_ms, metrics);
+        match first_correction {
+            Some(true) => released.push(Release::Correction { sequence, content }),
+            Some(false) => {}
+            None if self.exceeds_max_gap(self.next - sequence) => eprintln!(
+                "warning: discarding token {} for conversation {}, suspiciously far behind {}",
+                sequence, self.conversation_id, self.next
+            ),
+            None => eprintln!(
+                "warning: discarding late token {} for conversation {}",
+                sequence, self.conversation_id
+            ),
+        }
+        released
+    }
+
+    /// Accept a token without a sequence. It has no place to wait for, so it
+    /// is released at once, followed by anything else now ready.
+    pub fn push_unsequenced(&mut self, content: String, now_ms: u64, metrics: &mut Metrics) -> Vec<Release> {
+        self.last_activity_ms = now_ms;
+        let mut released = vec![Release::Unsequenced { content }];
+        released.extend(self.poll(now_ms, metrics));
+        released
+    }
+
+    /// Release ready tokens, skipping the current gap if it has timed out.
+    /// Call this periodically so a stalled stream still makes progress.
+    pub fn poll(&mut self, now_ms: u64, metrics: &mut Metrics) -> Vec<Release> {
+        let mut released = self.drain_ready();
+
+        if let Some(&first_pending) = self.pending.keys().next() {
+            let since = *self.gap_since_ms.get_or_insert(now_ms);
+            if now_ms.saturating_sub(since) >= self.gap_timeout_ms {
+                released.push(self.skip_to(first_pending, now_ms));
+                metrics.gap_timeouts += 1;
+                released.extend(self.drain_ready());
+                // A later gap gets its own full timeout
+                self.gap_since_ms = (!self.pending.is_empty()).then_some(now_ms);
+            }
+        }
+
+        metrics.set_buffer_depth(&self.conversation_id, self.depth());
+        released
+    }
+
+    /// Evict buffered tokens that have waited longer than the message TTL,
+    /// returning how many were evicted.
+    ///
+    /// `poll` only releases tokens by skipping gaps, so tokens buffered for
+    /// a conversation that has gone silent would otherwise stay forever.
+    /// Call this on the same periodic tick as `poll` (or use `gc_buffers`
+    /// for a whole set of conversations), and drop the buffer once
+    /// `is_stale` reports true.
+    pub fn gc(&mut self, now_ms: u64, metrics: &mut Metrics) -> usize {
+        self.expire_skipped(now_ms);
+        let Some(ttl) = self.message_ttl_ms else {
+            return 0;
+        };
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, pending| now_ms.saturating_sub(pending.arrived_ms) < ttl);
+        if self.pending.is_empty() {
+            self.gap_since_ms = None;
+        }
+        metrics.set_buffer_depth(&self.conversation_id, self.depth());
+        before - self.pending.len()
+    }
+
+    /// Whether the buffer holds nothing and has seen no tokens for the
+    /// message TTL, so it can be discarded. Never true without a TTL.
+    pub fn is_stale(&self, now_ms: u64) -> bool {
+        self.pending.is_empty()
+            && self
+                .message_ttl_ms
+                .is_some_and(|ttl| now_ms.saturating_sub(self.last_activity_ms) >= ttl)
+    }
+
+    /// Whether `gap` missing sequences is more than a reorder could explain.
+    fn exceeds_max_gap(&self, gap: u64) -> bool {
+        self.max_sequence_gap.is_some_and(|max| gap > max)
+    }
+
+    /// Skip the sequences before `first_pending`, remembering them for the
+    /// late grace window.
+    fn skip_to(&mut self, first_pending: u64, now_ms: u64) -> Release {
+        if self.late_grace_ms > 0 {
+            self.skipped.push(SkippedGap {
+                first: self.next,
+                last: first_pending - 1,
+                skipped_at_ms: now_ms,
+                corrected: BTreeSet::new(),
+            });
+        }
+        let gap = Release::Gap { first: self.next, last: first_pending - 1 };
+        self.next = first_pending;
+        gap
+    }
+
+    /// Forget skipped gaps whose grace window has passed.
+    fn expire_skipped(&mut self, now_ms: u64) {
+        let grace = self.late_grace_ms;
+        self.skipped
+            .retain(|gap| now_ms.saturating_sub(gap.skipped_at_ms) <= grace);
+    }
+
+    /// Pop tokens contiguous with the stream position.
+    fn drain_ready(&mut self) -> Vec<Release> {
+        let mut released = Vec::new();
+        while let Some(pending) = self.pending.remove(&self.next) {
+            released.push(Release::Token { sequence: self.next, content: pending.content });
+            self.next += 1;
+            self.gap_since_ms = None;
+        }
+        released
+    }
+}
+
+/// Run `gc` on every buffer, keyed by conversation id, and remove the
+/// stale ones. Returns the number of tokens evicted.
+pub fn gc_buffers(buffers: &mut HashMap<String, TokenBuffer>, now_ms: u64, metrics: &mut Metrics) -> usize {
+    let mut evicted = 0;
+    buffers.retain(|_, buffer| {
+        evicted += buffer.gc(now_ms, metrics);
+        !buffer.is_stale(now_ms)
+    });
+    evicted
+}
+
+/// Keep the buffers, keyed by conversation id, within `max_bytes` of
+/// buffered content in total, so a flood of conversations can't exhaust
+/// memory. The least recently active conversations are drained and removed
+/// until the total fits; their drains are returned, oldest first, for the
+/// caller to emit.
+pub fn enforce_budget(
+    buffers: &mut HashMap<String, TokenBuffer>,
+    max_bytes: usize,
+    metrics: &mut Metrics,
+) -> Vec<(String, Vec<Release>)> {
+    let mut total: usize = buffers.values().map(TokenBuffer::bytes).sum();
+    let mut by_age: Vec<(u64, String)> = buffers
+        .iter()
+        .map(|(id, buffer)| (buffer.last_activity_ms, id.clone()))
+        .collect();
+    by_age.sort();
+
+    let mut evicted = Vec::new();
+    for (_, id) in by_age {
+        if total <= max_bytes {
+            break;
+        }
+        let Some(mut buffer) = buffers.remove(&id) else {
+            continue;
+        };
+        total -= buffer.bytes();
+        eprintln!(
+            "warning: reassembly buffers over {} bytes, evicting conversation {} ({} bytes)",
+            max_bytes,
+            id,
+            buffer.bytes()
+        );
+        evicted.push((id, buffer.drain(metrics)));
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(sequence: u64, content: &str) -> Release {
+        Release::Token { sequence, content: content.to_string() }
+    }
+
+    #[test]
+    fn test_in_order_tokens_pass_straight_through() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100);
+        assert_eq!(buffer.push(1, "a".into(), 0, &mut metrics), vec![token(1, "a")]);
+        assert_eq!(buffer.push(2, "b".into(), 0, &mut metrics), vec![token(2, "b")]);
+        assert!(metrics.buffer_depth.is_empty());
+    }
+
+    #[test]
+    fn test_out_of_order_tokens_buffered_and_depth_reported() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100);
+        assert!(buffer.push(3, "c".into(), 0, &mut metrics).is_empty());
+        assert!(buffer.push(2, "b".into(), 10, &mut metrics).is_empty());
+        assert_eq!(metrics.buffer_depth["abc"], 2);
+
+        let released = buffer.push(1, "a".into(), 20, &mut metrics);
+        assert_eq!(released, vec![token(1, "a"), token(2, "b"), token(3, "c")]);
+        assert!(!metrics.buffer_depth.contains_key("abc"));
+        assert_eq!(metrics.gap_timeouts, 0);
+    }
+
+    #[test]
+    fn test_gap_times_out() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100);
+        assert!(buffer.push(3, "c".into(), 0, &mut metrics).is_empty());
+        assert!(buffer.poll(99, &mut metrics).is_empty());
+        assert_eq!(metrics.gap_timeouts, 0);
+
+        let released = buffer.poll(100, &mut metrics);
+        assert_eq!(released, vec![Release::Gap { first: 1, last: 2 }, token(3, "c")]);
+        assert_eq!(metrics.gap_timeouts, 1);
+        assert!(metrics.buffer_depth.is_empty());
+
+        // The skipped tokens are discarded if they turn up afterwards
+        assert!(buffer.push(2, "b".into(), 150, &mut metrics).is_empty());
+    }
+
+    #[test]
+    fn test_ttl_evicts_undeliverable_tokens() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 10_000).with_message_ttl_ms(Some(500));
+        buffer.push(3, "c".into(), 0, &mut metrics);
+        buffer.push(4, "d".into(), 300, &mut metrics);
+
+        assert_eq!(buffer.gc(499, &mut metrics), 0);
+        assert_eq!(buffer.gc(500, &mut metrics), 1);
+        assert_eq!(metrics.buffer_depth["abc"], 1);
+        assert_eq!(buffer.gc(800, &mut metrics), 1);
+        assert!(metrics.buffer_depth.is_empty());
+    }
+
+    #[test]
+    fn test_stale_buffers_cleaned_up() {
+        let mut metrics = Metrics::default();
+        let mut buffers = HashMap::new();
+        for id in ["quiet", "busy"] {
+            let buffer = TokenBuffer::new(id, 1, 10_000).with_message_ttl_ms(Some(500));
+            buffers.insert(id.to_string(), buffer);
+        }
+        buffers.get_mut("quiet").unwrap().push(5, "stuck".into(), 0, &mut metrics);
+        buffers.get_mut("busy").unwrap().push(1, "a".into(), 0, &mut metrics);
+
+        buffers.get_mut("busy").unwrap().push(2, "b".into(), 400, &mut metrics);
+        assert_eq!(gc_buffers(&mut buffers, 600, &mut metrics), 1);
+        assert_eq!(buffers.keys().collect::<Vec<_>>(), vec!["busy"]);
+        assert!(metrics.buffer_depth.is_empty());
+
+        gc_buffers(&mut buffers, 900, &mut metrics);
+        assert!(buffers.is_empty());
+    }
+
+    #[test]
+    fn test_without_ttl_nothing_is_evicted() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 10_000);
+        buffer.push(3, "c".into(), 0, &mut metrics);
+        assert_eq!(buffer.gc(u64::MAX, &mut metrics), 0);
+        assert!(!buffer.is_stale(u64::MAX));
+    }
+
+    fn skip_gap(buffer: &mut TokenBuffer, metrics: &mut Metrics) {
+        // Sequence 1 goes missing; 2 is released after the gap at t=100
+        assert!(buffer.push(2, "b".into(), 0, metrics).is_empty());
+        assert_eq!(
+            buffer.poll(100, metrics),
+            vec![Release::Gap { first: 1, last: 1 }, token(2, "b")]
+        );
+    }
+
+    #[test]
+    fn test_over_budget_evicts_oldest_conversation() {
+        let mut metrics = Metrics::default();
+        let mut buffers = HashMap::new();
+        for (id, now) in [("old", 0), ("mid", 10), ("new", 20)] {
+            let mut buffer = TokenBuffer::new(id, 1, 10_000);
+            buffer.push(3, "0123456789".into(), now, &mut metrics);
+            buffers.insert(id.to_string(), buffer);
+        }
+
+        assert!(enforce_budget(&mut buffers, 30, &mut metrics).is_empty());
+        let evicted = enforce_budget(&mut buffers, 25, &mut metrics);
+        assert_eq!(
+            evicted,
+            vec![("old".to_string(), vec![Release::Gap { first: 1, last: 2 }, token(3, "0123456789")])]
+        );
+        assert!(!buffers.contains_key("old"));
+        assert!(!metrics.buffer_depth.contains_key("old"));
+        assert_eq!(metrics.buffer_depth["mid"], 1);
+
+        let evicted = enforce_budget(&mut buffers, 0, &mut metrics);
+        let ids: Vec<&str> = evicted.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(ids, ["mid", "new"]);
+    }
+
+    #[test]
+    fn test_small_gap_buffered_within_max_gap() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100).with_max_sequence_gap(Some(2));
+        assert!(buffer.push(3, "c".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.depth(), 1);
+        assert_eq!(buffer.push(1, "a".into(), 10, &mut metrics)[0], token(1, "a"));
+    }
+
+    #[test]
+    fn test_huge_gap_skipped_immediately() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100).with_max_sequence_gap(Some(2));
+        assert!(buffer.push(3, "c".into(), 0, &mut metrics).is_empty());
+
+        let released = buffer.push(5000, "z".into(), 0, &mut metrics);
+        assert_eq!(
+            released,
+            vec![
+                Release::Gap { first: 1, last: 2 },
+                token(3, "c"),
+                Release::Gap { first: 4, last: 4999 },
+                token(5000, "z"),
+            ]
+        );
+        assert!(metrics.buffer_depth.is_empty());
+        assert_eq!(metrics.gap_timeouts, 0);
+
+        // Nor is a token far behind the stream position waited for
+        assert!(buffer.push(7, "g".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.push(5001, "y".into(), 0, &mut metrics), vec![token(5001, "y")]);
+    }
+
+    #[test]
+    fn test_zero_start_sequences() {
+        let mut metrics = Metrics::default();
+        let settings = Settings { sequence_start: 0, ..Settings::default() };
+        let mut buffer = TokenBuffer::from_settings("abc", &settings);
+        assert!(buffer.push(1, "b".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.push(0, "a".into(), 0, &mut metrics), vec![token(0, "a"), token(1, "b")]);
+    }
+
+    #[test]
+    fn test_one_start_sequences() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::from_settings("abc", &Settings::default());
+        // Without a zero start, a zero is behind the stream position
+        assert!(buffer.push(0, "x".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.push(1, "a".into(), 0, &mut metrics), vec![token(1, "a")]);
+    }
+
+    #[test]
+    fn test_unsequenced_tokens_pass_through_in_arrival_order() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::from_settings("abc", &Settings::default());
+        let unsequenced = |content: &str| Release::Unsequenced { content: content.into() };
+        assert_eq!(buffer.push_unsequenced("a".into(), 0, &mut metrics), vec![unsequenced("a")]);
+        assert_eq!(buffer.push_unsequenced("b".into(), 0, &mut metrics), vec![unsequenced("b")]);
+
+        // Nor do they wait behind a gap
+        assert!(buffer.push(2, "y".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.push_unsequenced("c".into(), 0, &mut metrics), vec![unsequenced("c")]);
+        assert_eq!(buffer.depth(), 1);
+    }
+
+    #[test]
+    fn test_on_time_token_needs_no_grace() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100).with_late_grace_ms(50);
+        assert!(buffer.push(2, "b".into(), 0, &mut metrics).is_empty());
+        assert_eq!(buffer.push(1, "a".into(), 99, &mut metrics), vec![token(1, "a"), token(2, "b")]);
+    }
+
+    #[test]
+    fn test_late_token_within_grace_is_a_correction() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100).with_late_grace_ms(50);
+        skip_gap(&mut buffer, &mut metrics);
+
+        let released = buffer.push(1, "a".into(), 150, &mut metrics);
+        assert_eq!(released, vec![Release::Correction { sequence: 1, content: "a".into() }]);
+        // A redelivery of the same token isn't corrected twice
+        assert!(buffer.push(1, "a".into(), 150, &mut metrics).is_empty());
+    }
+
+    #[test]
+    fn test_late_token_beyond_grace_is_discarded() {
+        let mut metrics = Metrics::default();
+        let mut buffer = TokenBuffer::new("abc", 1, 100).with_late_grace_ms(50);
+        skip_gap(&mut buffer, &mut metrics);
+        assert!(buffer.push(1, "a".into(), 151, &mut metrics).is_empty());
+    }
+}
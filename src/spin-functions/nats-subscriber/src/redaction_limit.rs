@@ -0,0 +1,82 @@
+// Per-conversation redaction limit. An LLM that keeps emitting secrets
+// suggests a compromised system prompt or data exfiltration, so once a
+// conversation has had more than `max_redactions_per_conversation`
+// redactions the stream is terminated: that message and every later one in
+// the conversation is dropped with `REDACTION_LIMIT`.
+
+use anyhow::Result;
+
+use crate::kv::{self, Store};
+use crate::{Action, InspectionResult};
+
+pub const REASON_CODE: &str = "REDACTION_LIMIT";
+
+fn key(scope: &str) -> String {
+    format!("redactions/{}", scope)
+}
+
+/// Count `result` against the conversation's redaction budget of `max`,
+/// returning the drop that replaces it once the budget is exceeded.
+pub fn check(store: &dyn Store, scope: &str, max: u64, result: &InspectionResult) -> Result<Option<InspectionResult>> {
+    let key = key(scope);
+    let mut count = kv::read_counter(store, &key)?;
+    if count <= max && result.action == Action::Redact {
+        count = kv::add_counter(store, &key, 1)?;
+    }
+    if count <= max {
+        return Ok(None);
+    }
+
+    let mut terminated = InspectionResult::drop(format!(
+        "Conversation exceeded {} redactions; stream terminated",
+        max
+    ))
+    .with_reason_code(REASON_CODE);
+    if result.action != Action::Allow {
+        terminated.secondary_actions = vec![result.action];
+    }
+    Ok(Some(terminated))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    fn redacted() -> InspectionResult {
+        InspectionResult::redact("secret".into(), "[REDACTED]".into())
+    }
+
+    fn apply(store: &MemoryStore, scope: &str, max: u64, result: InspectionResult) -> InspectionResult {
+        check(store, scope, max, &result).unwrap().unwrap_or(result)
+    }
+
+    #[test]
+    fn test_successive_redactions_terminate_stream() {
+        let store = MemoryStore::default();
+        for _ in 0..2 {
+            let result = apply(&store, "abc", 2, redacted());
+            assert_eq!(result.action, Action::Redact);
+        }
+        let result = apply(&store, "abc", 2, InspectionResult::allow());
+        assert_eq!(result.action, Action::Allow);
+
+        let result = apply(&store, "abc", 2, redacted());
+        assert_eq!(result.action, Action::Drop);
+        assert_eq!(result.reason_code.as_deref(), Some(REASON_CODE));
+        assert_eq!(result.secondary_actions, vec![Action::Redact]);
+
+        // Terminated: clean messages are dropped too
+        let result = apply(&store, "abc", 2, InspectionResult::allow());
+        assert_eq!(result.action, Action::Drop);
+        assert_eq!(result.reason_code.as_deref(), Some(REASON_CODE));
+    }
+
+    #[test]
+    fn test_conversations_counted_separately() {
+        let store = MemoryStore::default();
+        apply(&store, "abc", 0, redacted());
+        let result = apply(&store, "xyz", 1, redacted());
+        assert_eq!(result.action, Action::Redact);
+    }
+}
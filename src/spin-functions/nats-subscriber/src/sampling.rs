@@ -0,0 +1,94 @@
+// Deterministic sampling for high-volume, low-risk streams: only a fixed
+// fraction of tokens is fully inspected, chosen by hashing the sequence number
+// so every replica (and every retry) makes the same decision for a token. The
+// rest still get the detectors that drop content, such as canaries and
+// injection; only the costlier redacting ones are skipped.
+
+use crate::detectors;
+use crate::policy::Policy;
+use crate::Action;
+
+/// Resolution of the sample rate; rates are rounded to 1/BUCKETS.
+const BUCKETS: u64 = 10_000;
+
+/// Whether the token with this sequence number falls inside the sample.
+///
+/// A rate of 1.0 or more inspects everything and 0.0 or less nothing.
+/// Messages without a sequence number can't be sampled deterministically
+/// and are always inspected.
+pub fn should_inspect(sequence: Option<u64>, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    let Some(sequence) = sequence else {
+        return true;
+    };
+    let threshold = (sample_rate.max(0.0) * BUCKETS as f64).round() as u64;
+    mix(sequence) % BUCKETS < threshold
+}
+
+/// SplitMix64 finalizer: spreads consecutive sequence numbers evenly so a
+/// sample isn't just "every Nth token".
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// `policy` narrowed to its detectors that drop content, for a token
+/// outside the sample, or `None` if it has none.
+pub fn blocking_only(policy: &Policy) -> Option<Policy> {
+    let enabled_detectors: Vec<String> = detectors::ordered(policy)
+        .into_iter()
+        .filter(|detector| detector.action(policy) == Some(Action::Drop))
+        .map(|detector| detector.name().to_string())
+        .collect();
+    // An empty list would enable every detector
+    (!enabled_detectors.is_empty()).then(|| Policy { enabled_detectors, ..policy.clone() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inspected(rate: f64, count: u64) -> u64 {
+        (0..count).filter(|&seq| should_inspect(Some(seq), rate)).count() as u64
+    }
+
+    #[test]
+    fn test_fraction_inspected_matches_rate() {
+        for rate in [0.1, 0.25, 0.5, 0.9] {
+            let fraction = inspected(rate, 20_000) as f64 / 20_000.0;
+            assert!((fraction - rate).abs() < 0.02, "rate {} inspected {}", rate, fraction);
+        }
+    }
+
+    #[test]
+    fn test_bounds() {
+        assert_eq!(inspected(1.0, 1000), 1000);
+        assert_eq!(inspected(0.0, 1000), 0);
+    }
+
+    #[test]
+    fn test_decision_is_deterministic() {
+        for seq in 0..100 {
+            assert_eq!(should_inspect(Some(seq), 0.3), should_inspect(Some(seq), 0.3));
+        }
+    }
+
+    #[test]
+    fn test_blocking_only_keeps_dropping_detectors() {
+        let policy = blocking_only(&Policy::default()).unwrap();
+        assert!(policy.enabled_detectors.iter().any(|name| name == "injection"));
+        assert!(!policy.enabled_detectors.iter().any(|name| name == "keyword"));
+
+        let nothing_blocks = Policy { enabled_detectors: vec!["keyword".into()], ..Policy::default() };
+        assert_eq!(blocking_only(&nothing_blocks), None);
+    }
+
+    #[test]
+    fn test_unsequenced_messages_always_inspected() {
+        assert!(should_inspect(None, 0.0));
+    }
+}
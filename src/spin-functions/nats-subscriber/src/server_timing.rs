@@ -0,0 +1,54 @@
+// `Server-Timing` response header, so request phases show up in browser
+// devtools without a separate observability stack. Each phase is the time
+// since the previous mark, in milliseconds:
+//
+//   server-timing: parse;dur=0.120, inspect;dur=1.503, forward;dur=0.041
+
+use std::time::Duration;
+
+use crate::clock::Clock;
+
+pub const HEADER: &str = "server-timing";
+
+/// Durations of consecutive request phases.
+pub struct ServerTiming<'a> {
+    clock: &'a dyn Clock,
+    last: Duration,
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl<'a> ServerTiming<'a> {
+    pub fn start(clock: &'a dyn Clock) -> Self {
+        ServerTiming { clock, last: clock.now(), phases: Vec::new() }
+    }
+
+    /// End the phase `name`, which began at the previous mark.
+    pub fn mark(&mut self, name: &'static str) {
+        let now = self.clock.now();
+        self.phases.push((name, now.saturating_sub(self.last)));
+        self.last = now;
+    }
+
+    pub fn header_value(&self) -> String {
+        self.phases
+            .iter()
+            .map(|(name, duration)| format!("{};dur={:.3}", name, duration.as_secs_f64() * 1000.0))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::StepClock;
+
+    #[test]
+    fn test_header_value() {
+        let clock = StepClock::new(Duration::from_micros(1500));
+        let mut timing = ServerTiming::start(&clock);
+        timing.mark("parse");
+        timing.mark("inspect");
+        assert_eq!(timing.header_value(), "parse;dur=1.500, inspect;dur=1.500");
+    }
+}
@@ -0,0 +1,67 @@
+// Shadow policy evaluation. A ruleset being rolled out can run as the
+// `shadow_policy` against live traffic: each message's content is also
+// inspected under it, and where its verdict differs from the live one the
+// difference is logged. The live response is never affected.
+//
+// The shadow policy runs through the same stage of the pipeline as the live
+// one, adjusted for trust, warmup and the fast path alike, so the two differ
+// only where the policies do. Messages settled before the detectors run
+// (bypassed, sampled out, skipped) aren't compared. None of the per-message
+// side effects (counters, summaries, publishing) happen twice, so the only
+// cost is a second pass of the detectors.
+
+use crate::policy::Policy;
+use crate::{InspectionResult, NatsMessage};
+
+/// `shadow` with the runtime state `policy::select` gave the `live` policy:
+/// its canary tokens and redaction key.
+pub(crate) fn attach(shadow: &Policy, live: &Policy) -> Policy {
+    Policy {
+        canary_tokens: live.canary_tokens.clone(),
+        redaction_hash_key: live.redaction_hash_key.clone(),
+        ..shadow.clone()
+    }
+}
+
+/// The log line for `message` if the `shadowed` verdict differs from
+/// `live`, or `None` when they agree.
+pub(crate) fn compare(message: &NatsMessage, live: &InspectionResult, shadowed: &InspectionResult) -> Option<String> {
+    if shadowed.action == live.action && shadowed.reason_code == live.reason_code {
+        return None;
+    }
+    Some(format!(
+        "Shadow verdict differs on {} seq {:?}: live {} ({}), shadow {} ({})",
+        message.subject,
+        message.sequence,
+        live.action.as_str(),
+        live.reason_code.as_deref().unwrap_or("-"),
+        shadowed.action.as_str(),
+        shadowed.reason_code.as_deref().unwrap_or("-"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inspect_message;
+    use crate::test_support::NatsMessageBuilder;
+
+    #[test]
+    fn test_differences_reported() {
+        let live_policy = Policy::default();
+        let shadow = Policy { sensitive_patterns: vec!["internal".into()], ..Policy::default() };
+        let message = NatsMessageBuilder::new().subject("chat.abc.tokens").sequence(3).data("internal only").build();
+        let live = inspect_message(&message.data, &live_policy);
+        let shadowed = inspect_message(&message.data, &shadow);
+
+        assert_eq!(
+            compare(&message, &live, &shadowed).as_deref(),
+            Some("Shadow verdict differs on chat.abc.tokens seq Some(3): live allow (-), shadow redact (SENSITIVE_KEYWORD)")
+        );
+        // Agreement isn't logged
+        let message = NatsMessageBuilder::new().data("hello").build();
+        let live = inspect_message(&message.data, &live_policy);
+        let shadowed = inspect_message(&message.data, &shadow);
+        assert_eq!(compare(&message, &live, &shadowed), None);
+    }
+}
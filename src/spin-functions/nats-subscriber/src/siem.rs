@@ -0,0 +1,143 @@
+// Log lines for security events (drops and redactions) in a format a SIEM
+// can ingest without custom parsing: plain JSON, Elastic Common Schema, or
+// ArcSight Common Event Format. Content is never included, only the
+// verdict and where it happened.
+
+use serde_json::json;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::{Action, InspectionResult};
+
+/// ECS version the `ecs` format conforms to.
+pub const ECS_VERSION: &str = "8.11.0";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Json,
+    /// Elastic Common Schema.
+    Ecs,
+    /// ArcSight Common Event Format.
+    Cef,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "json" => Ok(LogFormat::Json),
+            "ecs" => Ok(LogFormat::Ecs),
+            "cef" => Ok(LogFormat::Cef),
+            other => anyhow::bail!("unknown log_format '{}', expected 'json', 'ecs' or 'cef'", other),
+        }
+    }
+}
+
+/// The log line for the verdict on a message published to `subject`.
+pub fn event(format: LogFormat, subject: &str, result: &InspectionResult, now_ms: u64) -> String {
+    let action = result.action.as_str();
+    let reason_code = result.reason_code.as_deref().unwrap_or("UNKNOWN");
+    let reason = result.reason.as_deref().unwrap_or(reason_code);
+    let category = result.category.as_deref();
+    match format {
+        LogFormat::Json => json!({
+            "event": action,
+            "subject": subject,
+            "reason_code": reason_code,
+            "reason": reason,
+            "category": category,
+            "timestamp_ms": now_ms,
+        })
+        .to_string(),
+        LogFormat::Ecs => json!({
+            "@timestamp": humantime::format_rfc3339_millis(UNIX_EPOCH + Duration::from_millis(now_ms)).to_string(),
+            "ecs": { "version": ECS_VERSION },
+            "message": reason,
+            "event": {
+                "kind": "alert",
+                "category": ["intrusion_detection"],
+                "type": [if result.action == Action::Drop { "denied" } else { "change" }],
+                "action": action,
+                "reason": reason,
+            },
+            "rule": { "name": reason_code, "category": category },
+            "labels": { "nats_subject": subject },
+        })
+        .to_string(),
+        LogFormat::Cef => {
+            let severity = if result.action == Action::Drop { 8 } else { 5 };
+            let mut extension = format!("act={} rt={} cs1Label=subject cs1={}", action, now_ms, cef_value(subject));
+            if let Some(category) = category {
+                extension.push_str(&format!(" cat={}", cef_value(category)));
+            }
+            format!(
+                "CEF:0|distributed-sse|nats-subscriber|{}|{}|{}|{}|{}",
+                env!("CARGO_PKG_VERSION"),
+                cef_header(reason_code),
+                cef_header(reason),
+                severity,
+                extension
+            )
+        }
+    }
+}
+
+/// Escape a CEF header field, where `|` separates fields.
+fn cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+/// Escape a CEF extension value, where `=` separates keys from values.
+fn cef_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn injection_drop() -> InspectionResult {
+        let mut result = InspectionResult::drop("Potential prompt injection: ignore previous".into())
+            .with_reason_code("PROMPT_INJECTION");
+        result.category = Some("injection".into());
+        result
+    }
+
+    #[test]
+    fn test_ecs_event() {
+        let line = event(LogFormat::Ecs, "chat.abc.tokens", &injection_drop(), 1_700_000_000_123);
+        let event: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(event["@timestamp"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(event["event"]["action"], "drop");
+        assert_eq!(event["event"]["type"][0], "denied");
+        assert_eq!(event["rule"]["name"], "PROMPT_INJECTION");
+        assert_eq!(event["rule"]["category"], "injection");
+        assert_eq!(event["labels"]["nats_subject"], "chat.abc.tokens");
+    }
+
+    #[test]
+    fn test_cef_event() {
+        let line = event(LogFormat::Cef, "chat.abc.tokens", &injection_drop(), 1_700_000_000_123);
+        assert_eq!(
+            line,
+            format!(
+                "CEF:0|distributed-sse|nats-subscriber|{}|PROMPT_INJECTION|Potential prompt injection: ignore previous|8|\
+                 act=drop rt=1700000000123 cs1Label=subject cs1=chat.abc.tokens cat=injection",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn test_cef_escaping() {
+        let result = InspectionResult::drop("a|b".into()).with_reason_code("X");
+        let line = event(LogFormat::Cef, "odd=subject", &result, 0);
+        assert!(line.contains("|a\\|b|"));
+        assert!(line.contains("cs1=odd\\=subject"));
+    }
+}
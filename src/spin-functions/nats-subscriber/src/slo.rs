@@ -0,0 +1,87 @@
+// Latency SLO with shed-on-breach. Inspection sits on the SSE path, so with
+// `slo_target_ms` set the latency of each inspection is kept in a rolling
+// window of the last `slo_window` samples in KV. While the window's average
+// is over the target, messages on the low-risk `slo_fast_path_subjects` are
+// inspected with only the cheap detectors, the same reduced set trusted
+// producers get, until the average comes back under it. Other subjects keep
+// full inspection throughout.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::kv::Store;
+use crate::policy::Policy;
+use crate::signature;
+
+const KEY: &str = "slo_latency";
+
+/// The most recent inspection latencies, oldest first.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    samples_ms: VecDeque<u64>,
+}
+
+impl Window {
+    /// Load the stored window; a missing entry reads as empty.
+    pub fn load(store: &dyn Store) -> Result<Self> {
+        match store.get(KEY)? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Window::default()),
+        }
+    }
+
+    pub fn save(&self, store: &dyn Store) -> Result<()> {
+        store.set(KEY, &serde_json::to_vec(self)?)
+    }
+
+    /// Add a sample, keeping the latest `size`.
+    pub fn observe(&mut self, latency_ms: u64, size: usize) {
+        self.samples_ms.push_back(latency_ms);
+        while self.samples_ms.len() > size.max(1) {
+            self.samples_ms.pop_front();
+        }
+    }
+
+    /// Mean of the samples, 0 when there are none.
+    pub fn average_ms(&self) -> u64 {
+        match self.samples_ms.len() as u64 {
+            0 => 0,
+            count => self.samples_ms.iter().sum::<u64>() / count,
+        }
+    }
+
+    /// Whether recent inspections are averaging over `target_ms`.
+    pub fn breached(&self, target_ms: u64) -> bool {
+        self.average_ms() > target_ms
+    }
+}
+
+/// `policy` for a message shed onto the fast path.
+pub fn fast_path(policy: &Policy) -> Policy {
+    signature::reduced(policy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_window_rolls() {
+        let mut window = Window::default();
+        assert!(!window.breached(0));
+        for latency_ms in [100, 100, 10, 10] {
+            window.observe(latency_ms, 3);
+        }
+        assert_eq!(window.average_ms(), 40);
+        assert!(window.breached(30));
+        window.observe(10, 3);
+        assert!(!window.breached(30));
+
+        let store = MemoryStore::default();
+        window.save(&store).unwrap();
+        assert_eq!(Window::load(&store).unwrap(), window);
+    }
+}
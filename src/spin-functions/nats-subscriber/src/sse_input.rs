@@ -0,0 +1,173 @@
+// SSE input: for deployments that receive the LLM output as an SSE stream
+// themselves. The upstream event stream is parsed frame by frame, each
+// frame's data is inspected, and the verdicts go back out through a
+// `Gateway` as a sanitized stream.
+
+use crate::formats;
+use crate::gateway::Gateway;
+use crate::policy::Policy;
+
+/// Request header naming the content type of every frame's data, e.g.
+/// `formats::OPENAI_CHUNK` for a chat-completion stream; the data is plain
+/// text without it.
+pub const DATA_CONTENT_TYPE_HEADER: &str = "x-data-content-type";
+
+/// One upstream event. `id` is kept only when numeric, because the gateway
+/// numbers frames by sequence.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Frame {
+    pub event: Option<String>,
+    pub id: Option<u64>,
+    pub data: String,
+}
+
+/// Incremental `text/event-stream` parser. Bytes go in as they arrive, in
+/// chunks split anywhere; complete frames come out. Fields other than
+/// `data`, `event` and `id` are ignored, as the spec requires. A frame that
+/// can't be used (no data, or invalid UTF-8) comes out as an error
+/// describing it.
+#[derive(Debug, Default)]
+pub struct Parser {
+    /// Bytes after the last complete line.
+    partial: Vec<u8>,
+    /// Lines of the frame being read.
+    lines: Vec<Vec<u8>>,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Parser::default()
+    }
+
+    /// Feed the next chunk, returning the frames it completes.
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Result<Frame, String>> {
+        self.partial.extend_from_slice(chunk);
+        let mut frames = Vec::new();
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let mut line: Vec<u8> = self.partial.drain(..=end).collect();
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.is_empty() {
+                if !self.lines.is_empty() {
+                    frames.push(parse_frame(std::mem::take(&mut self.lines)));
+                }
+            } else if !line.starts_with(b":") {
+                // Comments, such as keepalives, aren't part of the frame
+                self.lines.push(line);
+            }
+        }
+        frames
+    }
+
+    /// End of input. A frame not closed by a blank line is incomplete, and
+    /// reported as malformed.
+    pub fn finish(&mut self) -> Option<String> {
+        if !self.partial.is_empty() {
+            self.lines.push(std::mem::take(&mut self.partial));
+        }
+        if self.lines.is_empty() {
+            return None;
+        }
+        self.lines.clear();
+        Some("stream ended inside a frame".to_string())
+    }
+}
+
+fn parse_frame(lines: Vec<Vec<u8>>) -> Result<Frame, String> {
+    let mut frame = Frame::default();
+    let mut data: Option<Vec<&str>> = None;
+    let lines = lines
+        .iter()
+        .map(|line| std::str::from_utf8(line))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("frame isn't UTF-8: {}", e))?;
+    for line in lines {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value);
+        match field {
+            "data" => data.get_or_insert_with(Vec::new).push(value),
+            "event" => frame.event = Some(value.to_string()),
+            "id" => frame.id = value.parse().ok(),
+            _ => {}
+        }
+    }
+    frame.data = data.ok_or("frame has no data")?.join("\n");
+    Ok(frame)
+}
+
+/// Inspect the data of every frame, as `content_type`, under `policy` and
+/// return the sanitized stream, skipping malformed frames and closing it at
+/// an upstream `[DONE]` or the end of input.
+pub fn reinspect(body: &[u8], content_type: Option<&str>, policy: &Policy, gateway: &mut Gateway) -> String {
+    let mut parser = Parser::new();
+    let mut out = String::new();
+    for frame in parser.feed(body) {
+        let frame = match frame {
+            Ok(frame) => frame,
+            Err(e) => {
+                eprintln!("warning: skipping malformed SSE frame: {}", e);
+                continue;
+            }
+        };
+        if frame.data == "[DONE]" {
+            break;
+        }
+        let result = formats::inspect_payload(&frame.data, content_type, policy);
+        out.push_str(&gateway.push(frame.id, &frame.data, &result));
+    }
+    if let Some(e) = parser.finish() {
+        eprintln!("warning: skipping malformed SSE frame: {}", e);
+    }
+    out.push_str(&gateway.finish());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gateway::DONE_FRAME;
+
+    #[test]
+    fn test_frames_parsed_across_chunk_boundaries() {
+        let input = b"event: message\r\nid: 7\r\ndata: hello\r\ndata: world\r\n\r\n: keepalive\n\nevent: ping\n\ndata: next\n\n";
+        let mut parser = Parser::new();
+        let mut frames = Vec::new();
+        for chunk in input.chunks(5) {
+            frames.extend(parser.feed(chunk));
+        }
+        assert_eq!(
+            frames,
+            [
+                Ok(Frame { event: Some("message".into()), id: Some(7), data: "hello\nworld".into() }),
+                Err("frame has no data".into()),
+                Ok(Frame { event: None, id: None, data: "next".into() }),
+            ]
+        );
+        assert_eq!(parser.finish(), None);
+    }
+
+    #[test]
+    fn test_unknown_fields_ignored() {
+        let mut parser = Parser::new();
+        let frames = parser.feed(b"retry: 3000\nx-upstream: a1\ndata: hello\nnot a field\n\n");
+        assert_eq!(frames, [Ok(Frame { event: None, id: None, data: "hello".into() })]);
+    }
+
+    #[test]
+    fn test_malformed_frames_skipped() {
+        let input = b"not a field\n\ndata: \xff\n\ndata: fine\n\ndata: cut off";
+        let out = reinspect(input, None, &Policy::default(), &mut Gateway::default());
+        assert_eq!(out, format!("event: token\ndata: fine\n\n{}", DONE_FRAME));
+    }
+
+    #[test]
+    fn test_frame_data_inspected_as_content_type() {
+        let chunk = r#"{"choices":[{"delta":{"content":"my password"}}]}"#;
+        let input = format!("data: {}\n\n", chunk);
+        let out = reinspect(input.as_bytes(), Some(formats::OPENAI_CHUNK), &Policy::default(), &mut Gateway::default());
+        let redacted = r#"{"choices":[{"delta":{"content":"[REDACTED]"}}]}"#;
+        assert_eq!(out, format!("event: redacted\ndata: {}\n\n{}", redacted, DONE_FRAME));
+    }
+}
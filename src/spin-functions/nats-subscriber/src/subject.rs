@@ -0,0 +1,198 @@
+// NATS subject conventions. Token streams are published on
+// `chat.{conversation_id}.tokens`, with siblings such as
+// `chat.{conversation_id}.control` sharing the same prefix.
+
+use sha2::{Digest, Sha256};
+
+/// Scope used by per-conversation features for subjects with no
+/// conversation id. `*` can never be a real id, so it can't collide.
+pub const GLOBAL_SCOPE: &str = "*";
+
+/// Extract the conversation id from a `chat.{id}.*` subject, or `None` for
+/// subjects that don't follow the convention.
+pub fn conversation_id(subject: &str) -> Option<&str> {
+    let mut tokens = subject.split('.');
+    match (tokens.next(), tokens.next(), tokens.next()) {
+        (Some("chat"), Some(id), Some(_)) if !id.is_empty() && id != "*" && id != ">" => Some(id),
+        _ => None,
+    }
+}
+
+/// Control messages for a conversation's stream, recognized by subject
+/// suffix. They are delivered at once rather than in sequence order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    /// `chat.{id}.done`: the producer has finished the response.
+    Done,
+    /// `chat.{id}.cancel`: the frontend stopped the response.
+    Cancel,
+    /// `chat.{id}.error`: generation failed; data describes the failure.
+    Error,
+}
+
+/// The conversation and control message a `chat.{id}.{done,cancel,error}`
+/// subject carries, if it is one.
+pub fn control(subject: &str) -> Option<(&str, Control)> {
+    let id = conversation_id(subject)?;
+    let control = match subject.strip_prefix("chat.")?.strip_prefix(id)?.strip_prefix('.')? {
+        "done" => Control::Done,
+        "cancel" => Control::Cancel,
+        "error" => Control::Error,
+        _ => return None,
+    };
+    Some((id, control))
+}
+
+/// The conversation a `chat.{id}.cancel` subject cancels, if it is one.
+/// The frontend publishes there when the user stops the response.
+pub fn cancelled_conversation(subject: &str) -> Option<&str> {
+    control(subject).and_then(|(id, control)| (control == Control::Cancel).then_some(id))
+}
+
+/// The conversation id, falling back to `GLOBAL_SCOPE` so features keyed
+/// by conversation still apply (shared) to unconventional subjects.
+pub fn scope(subject: &str) -> &str {
+    conversation_id(subject).unwrap_or(GLOBAL_SCOPE)
+}
+
+/// Prefix of NATS request/reply inboxes unless clients configure their own.
+pub const DEFAULT_INBOX_PREFIX: &str = "_INBOX";
+
+/// Whether `subject` is a reply inbox under `prefix`, e.g.
+/// `_INBOX.k3j2x9.1`: the prefix, then at least one more token.
+pub fn is_inbox(subject: &str, prefix: &str) -> bool {
+    subject
+        .strip_prefix(prefix)
+        .and_then(|rest| rest.strip_prefix('.'))
+        .is_some_and(|rest| !rest.is_empty() && suspicious(subject).is_none())
+}
+
+/// Response header carrying `shard_key`, so a bridge or load balancer can
+/// hash a conversation's tokens onto the instance holding its reassembly
+/// state.
+pub const SHARD_KEY_HEADER: &str = "x-shard-key";
+
+/// Routing key for a conversation: a truncated SHA-256 of its id, stable
+/// across instances and releases (unlike `std`'s hasher).
+pub fn shard_key(conversation_id: &str) -> String {
+    Sha256::digest(conversation_id.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Why `subject` can't be a legitimate published subject, if it can't.
+///
+/// NATS subjects are dot-separated tokens with no whitespace or control
+/// characters, and wildcards are only valid when subscribing. Anything else
+/// was crafted, e.g. to forge log lines or match routing patterns.
+pub fn suspicious(subject: &str) -> Option<&'static str> {
+    if subject.chars().any(char::is_control) {
+        Some("control character in subject")
+    } else if subject.chars().any(char::is_whitespace) {
+        Some("whitespace in subject")
+    } else if subject.split('.').any(|token| token.is_empty() || token == "*" || token == ">") {
+        Some("empty or wildcard token in subject")
+    } else {
+        None
+    }
+}
+
+/// Whether `subject` matches a NATS subject `pattern`, where `*` matches
+/// exactly one token and a trailing `>` matches one or more tokens.
+pub fn matches(pattern: &str, subject: &str) -> bool {
+    let mut subject_tokens = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject_tokens.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (literal, Some(actual)) if literal == actual => {}
+            _ => return false,
+        }
+    }
+    subject_tokens.next().is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shard_key_is_stable() {
+        // A fixed function of the id: SHA-256("abc") starts ba7816bf8f01cfea
+        assert_eq!(shard_key("abc"), "ba7816bf8f01cfea");
+        assert_eq!(shard_key("abc"), shard_key("abc"));
+        assert_ne!(shard_key("abd"), shard_key("abc"));
+    }
+
+    #[test]
+    fn test_matches_wildcards() {
+        assert!(matches("chat.*.system", "chat.abc.system"));
+        assert!(!matches("chat.*.system", "chat.abc.tokens"));
+        assert!(!matches("chat.*.system", "chat.abc.system.extra"));
+        assert!(matches("internal.>", "internal.audit.log"));
+        assert!(!matches("internal.>", "internal"));
+        assert!(matches("chat.abc.tokens", "chat.abc.tokens"));
+        assert!(!matches("chat.abc", "chat.abc.tokens"));
+    }
+
+    #[test]
+    fn test_suspicious_subjects() {
+        assert_eq!(suspicious("chat.abc.tokens"), None);
+        assert!(suspicious("chat.abc.tokens\nINFO forged log line").is_some());
+        assert!(suspicious("chat.abc\0.tokens").is_some());
+        assert!(suspicious("chat.abc tokens").is_some());
+        assert!(suspicious("chat.*.tokens").is_some());
+        assert!(suspicious("chat..tokens").is_some());
+    }
+
+    #[test]
+    fn test_conversation_id() {
+        assert_eq!(conversation_id("chat.abc123.tokens"), Some("abc123"));
+        assert_eq!(conversation_id("chat.abc123.control"), Some("abc123"));
+    }
+
+    #[test]
+    fn test_conversation_id_rejects_other_shapes() {
+        assert!(conversation_id("chat.abc123").is_none());
+        assert!(conversation_id("chat..tokens").is_none());
+        assert!(conversation_id("chat.*.tokens").is_none());
+        assert!(conversation_id("inspection.abc.results").is_none());
+        assert!(conversation_id("broadcast").is_none());
+    }
+
+    #[test]
+    fn test_cancelled_conversation() {
+        assert_eq!(cancelled_conversation("chat.abc123.cancel"), Some("abc123"));
+        assert!(cancelled_conversation("chat.abc123.tokens").is_none());
+        assert!(cancelled_conversation("chat.abc123.cancel.extra").is_none());
+    }
+
+    #[test]
+    fn test_control_subjects() {
+        assert_eq!(control("chat.abc123.done"), Some(("abc123", Control::Done)));
+        assert_eq!(control("chat.abc123.error"), Some(("abc123", Control::Error)));
+        assert_eq!(control("chat.abc123.cancel"), Some(("abc123", Control::Cancel)));
+        assert!(control("chat.abc123.tokens").is_none());
+        assert!(control("chat.abc123.done.extra").is_none());
+    }
+
+    #[test]
+    fn test_inbox_subjects() {
+        assert!(is_inbox("_INBOX.k3j2x9.1", DEFAULT_INBOX_PREFIX));
+        assert!(is_inbox("_INBOX.k3j2x9", DEFAULT_INBOX_PREFIX));
+        assert!(is_inbox("_R_.app.k3j2x9", "_R_.app"));
+        assert!(!is_inbox("_INBOX", DEFAULT_INBOX_PREFIX));
+        assert!(!is_inbox("_INBOX.", DEFAULT_INBOX_PREFIX));
+        assert!(!is_inbox("_INBOXES.k3j2x9", DEFAULT_INBOX_PREFIX));
+        assert!(!is_inbox("_INBOX.*", DEFAULT_INBOX_PREFIX));
+        assert!(!is_inbox("chat.abc.tokens", DEFAULT_INBOX_PREFIX));
+        assert!(conversation_id("_INBOX.k3j2x9.1").is_none());
+    }
+
+    #[test]
+    fn test_scope_falls_back_to_global() {
+        assert_eq!(scope("chat.abc123.tokens"), "abc123");
+        assert_eq!(scope("broadcast"), GLOBAL_SCOPE);
+    }
+}
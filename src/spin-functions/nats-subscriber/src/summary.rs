@@ -0,0 +1,78 @@
+// Per-conversation summaries. With `summary_bridge_url` set, every verdict
+// on a `chat.{id}.*` subject is tallied in KV; when the conversation's SSE
+// stream closes, the gateway publishes the tally to `chat.{id}.summary`
+// and clears it, giving operators one line per conversation.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::kv::Store;
+use crate::outbound::Outbound;
+use crate::{Action, InspectionResult};
+
+/// Last subject token of the summary subject.
+pub const SUMMARY_TOKEN: &str = "summary";
+
+fn key(conversation_id: &str) -> String {
+    format!("summary/{}", conversation_id)
+}
+
+pub fn subject(conversation_id: &str) -> String {
+    format!("chat.{}.{}", conversation_id, SUMMARY_TOKEN)
+}
+
+/// Aggregates for one conversation so far.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Summary {
+    pub tokens: u64,
+    pub redactions: u64,
+    pub drops: u64,
+    pub reason_codes: BTreeSet<String>,
+}
+
+impl Summary {
+    pub fn load(store: &dyn Store, conversation_id: &str) -> Result<Self> {
+        match store.get(&key(conversation_id))? {
+            Some(raw) => Ok(serde_json::from_slice(&raw)?),
+            None => Ok(Summary::default()),
+        }
+    }
+
+    fn add(&mut self, result: &InspectionResult) {
+        self.tokens += 1;
+        match result.action {
+            Action::Allow | Action::Highlight => {}
+            Action::Redact => self.redactions += 1,
+            Action::Drop => self.drops += 1,
+        }
+        self.reason_codes.extend(result.reason_code.clone());
+    }
+}
+
+/// Count one verdict for the conversation.
+pub fn record(store: &dyn Store, conversation_id: &str, result: &InspectionResult) -> Result<()> {
+    let mut summary = Summary::load(store, conversation_id)?;
+    summary.add(result);
+    store.set(&key(conversation_id), &serde_json::to_vec(&summary)?)
+}
+
+/// Event published when a conversation's stream closes.
+#[derive(Debug, Serialize)]
+struct SummaryEvent<'a> {
+    conversation_id: &'a str,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+/// Publish the conversation's summary through the bridge and clear it.
+pub fn publish(store: &dyn Store, outbound: &dyn Outbound, bridge_url: &str, conversation_id: &str) -> Result<Summary> {
+    let summary = Summary::load(store, conversation_id)?;
+    let event = SummaryEvent { conversation_id, summary: &summary };
+    let url = format!("{}/publish/{}", bridge_url.trim_end_matches('/'), subject(conversation_id));
+    let (status, _) = outbound.post(&url, "application/json", serde_json::to_vec(&event)?)?;
+    anyhow::ensure!((200..300).contains(&status), "bridge returned {}", status);
+    store.delete(&key(conversation_id))?;
+    Ok(summary)
+}
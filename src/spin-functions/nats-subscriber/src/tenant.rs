@@ -0,0 +1,90 @@
+// Per-tenant rate limits. The bridge names the tenant a request is for in
+// the `x-tenant-id` header, and each tenant with a limit in KV gets its own
+// token bucket, so one noisy tenant can't starve the others. This sits
+// alongside the per-conversation in-flight cap rather than replacing it.
+//
+// Like the in-flight counts, buckets are read-modify-write in KV with no
+// atomicity, so concurrent requests can overspend slightly.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::kv::Store;
+
+/// Request header naming the tenant.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// A tenant's SLA: `rate` messages a second on average, in bursts of up to
+/// `burst`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Limit {
+    pub rate: f64,
+    pub burst: u64,
+}
+
+/// Bucket state: tokens left as of `updated_ms`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Bucket {
+    tokens: f64,
+    updated_ms: u64,
+}
+
+fn limit_key(tenant: &str) -> String {
+    format!("tenant/{}/limit", tenant)
+}
+
+fn bucket_key(tenant: &str) -> String {
+    format!("tenant/{}/bucket", tenant)
+}
+
+/// Store a tenant's limit in KV, replacing any previous one.
+pub fn save_limit(store: &dyn Store, tenant: &str, limit: Limit) -> Result<()> {
+    store.set(&limit_key(tenant), &serde_json::to_vec(&limit)?)
+}
+
+/// Spend `cost` tokens from the tenant's bucket, returning whether it had
+/// enough. Tenants without a stored limit are unlimited. A request that is
+/// refused spends nothing.
+pub fn admit(store: &dyn Store, tenant: &str, cost: u64, now_ms: u64) -> Result<bool> {
+    let Some(raw) = store.get(&limit_key(tenant))? else {
+        return Ok(true);
+    };
+    let limit: Limit = serde_json::from_slice(&raw)?;
+    let burst = limit.burst as f64;
+    let bucket = match store.get(&bucket_key(tenant))? {
+        Some(raw) => serde_json::from_slice(&raw)?,
+        None => Bucket { tokens: burst, updated_ms: now_ms },
+    };
+    let elapsed_secs = now_ms.saturating_sub(bucket.updated_ms) as f64 / 1000.0;
+    let tokens = (bucket.tokens + elapsed_secs * limit.rate).min(burst);
+    let cost = cost as f64;
+    let admitted = tokens >= cost;
+    let tokens = if admitted { tokens - cost } else { tokens };
+    store.set(&bucket_key(tenant), &serde_json::to_vec(&Bucket { tokens, updated_ms: now_ms })?)?;
+    Ok(admitted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_bucket_refills_at_rate() {
+        let store = MemoryStore::default();
+        save_limit(&store, "acme", Limit { rate: 2.0, burst: 2 }).unwrap();
+        assert!(admit(&store, "acme", 2, 0).unwrap());
+        assert!(!admit(&store, "acme", 1, 0).unwrap());
+        assert!(!admit(&store, "acme", 1, 400).unwrap());
+        assert!(admit(&store, "acme", 1, 500).unwrap());
+        // Refills stop at the burst
+        assert!(!admit(&store, "acme", 3, 60_000).unwrap());
+        assert!(admit(&store, "acme", 2, 60_000).unwrap());
+    }
+
+    #[test]
+    fn test_tenant_without_limit_unlimited() {
+        let store = MemoryStore::default();
+        assert!((0..1000).all(|_| admit(&store, "free", 1, 0).unwrap()));
+    }
+}
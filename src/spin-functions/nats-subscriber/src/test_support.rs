@@ -0,0 +1,158 @@
+// Deterministic fixtures shared by the unit tests, so tests don't
+// hand-assemble NatsMessage JSON and pick up new fields automatically.
+
+use spin_common::telemetry::Tracer;
+use spin_sdk::http::{Method, Request};
+
+use crate::clock::SystemClock;
+use crate::config::Settings;
+use crate::kv::Store;
+use crate::outbound::MockOutbound;
+use crate::{signature, Env, NatsMessage};
+
+/// Builder for `NatsMessage` fixtures. Defaults to a clean token on
+/// `chat.test.tokens` with no optional metadata.
+#[derive(Debug, Clone)]
+pub struct NatsMessageBuilder {
+    message: NatsMessage,
+}
+
+impl NatsMessageBuilder {
+    pub fn new() -> Self {
+        NatsMessageBuilder {
+            message: NatsMessage {
+                subject: "chat.test.tokens".to_string(),
+                data: "hello".to_string(),
+                sequence: None,
+                timestamp: None,
+                content_type: None,
+                signature: None,
+                reply: None,
+            },
+        }
+    }
+
+    pub fn subject(mut self, subject: &str) -> Self {
+        self.message.subject = subject.to_string();
+        self
+    }
+
+    pub fn data(mut self, data: &str) -> Self {
+        self.message.data = data.to_string();
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u64) -> Self {
+        self.message.sequence = Some(sequence);
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: i64) -> Self {
+        self.message.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.message.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn reply(mut self, reply: &str) -> Self {
+        self.message.reply = Some(reply.to_string());
+        self
+    }
+
+    /// Sign the message as it is now with `key`, as a trusted producer
+    /// would; set the subject and data first.
+    pub fn signed(mut self, key: &str) -> Self {
+        self.message.signature = Some(signature::sign(key, &self.message.subject, &self.message.data));
+        self
+    }
+
+    pub fn build(self) -> NatsMessage {
+        self.message
+    }
+
+    /// The message as the bridge would POST it.
+    pub fn json(&self) -> String {
+        serde_json::to_string(&self.message).expect("NatsMessage serializes")
+    }
+
+    /// A `POST /inspect` request carrying the message.
+    pub fn request(&self) -> Request {
+        Request::builder()
+            .method(Method::Post)
+            .uri("/inspect")
+            .body(self.json())
+            .build()
+    }
+}
+
+impl Default for NatsMessageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An `Env` for handler tests: outbound requests fail, nothing is traced,
+/// and time is the system clock. Override fields with
+/// `Env { clock: &clock, ..test_env(&settings, &store) }`.
+pub fn test_env<'a>(settings: &'a Settings, store: &'a dyn Store) -> Env<'a> {
+    // Leaked so the fixture can lend them out; tests are short-lived
+    let outbound = Box::leak(Box::new(MockOutbound::unreachable()));
+    let tracer = Box::leak(Box::new(Tracer::noop()));
+    let metrics = Box::leak(Box::default());
+    Env { settings, store, outbound, tracer, clock: &SystemClock, metrics }
+}
+
+/// JSON body for a batch of messages.
+pub fn batch_json(messages: &[NatsMessageBuilder]) -> String {
+    let messages: Vec<&NatsMessage> = messages.iter().map(|m| &m.message).collect();
+    serde_json::to_string(&messages).expect("NatsMessage serializes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_sets_fields() {
+        let message = NatsMessageBuilder::new()
+            .subject("chat.abc.tokens")
+            .data("hi")
+            .sequence(7)
+            .timestamp(1_700_000_000)
+            .content_type("text/plain")
+            .build();
+        assert_eq!(message.subject, "chat.abc.tokens");
+        assert_eq!(message.data, "hi");
+        assert_eq!(message.sequence, Some(7));
+        assert_eq!(message.timestamp, Some(1_700_000_000));
+        assert_eq!(message.content_type.as_deref(), Some("text/plain"));
+    }
+
+    #[test]
+    fn test_json_round_trips() {
+        let builder = NatsMessageBuilder::new().data("hi").sequence(3);
+        let parsed: NatsMessage = serde_json::from_str(&builder.json()).unwrap();
+        assert_eq!(parsed, builder.build());
+    }
+
+    #[test]
+    fn test_json_omits_unset_metadata() {
+        let json: serde_json::Value = serde_json::from_str(&NatsMessageBuilder::new().json()).unwrap();
+        assert_eq!(json, serde_json::json!({ "subject": "chat.test.tokens", "data": "hello" }));
+    }
+
+    #[test]
+    fn test_request_and_batch() {
+        let request = NatsMessageBuilder::new().request();
+        assert_eq!(request.path(), "/inspect");
+        let body: serde_json::Value = serde_json::from_slice(request.body()).unwrap();
+        assert_eq!(body["data"], "hello");
+
+        let batch = batch_json(&[NatsMessageBuilder::new(), NatsMessageBuilder::new().data("x")]);
+        let batch: serde_json::Value = serde_json::from_str(&batch).unwrap();
+        assert_eq!(batch[1]["data"], "x");
+    }
+}
@@ -0,0 +1,59 @@
+// Pre-inspection translation. Injection patterns are written in English, so
+// multilingual deployments can translate content to English first and run
+// the detectors over the translation while the original is forwarded.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::outbound::Outbound;
+
+#[derive(Serialize)]
+struct TranslateRequest<'a> {
+    text: &'a str,
+    target: &'a str,
+}
+
+#[derive(Deserialize)]
+struct TranslateResponse {
+    translation: String,
+}
+
+/// Language content is translated into before inspection.
+pub const TARGET_LANGUAGE: &str = "en";
+
+/// Ask the translation service at `url` for an English rendering of `text`.
+///
+/// The service receives `{"text": ..., "target": "en"}` and must answer
+/// `{"translation": ...}` with a 2xx status.
+pub fn translate(outbound: &dyn Outbound, url: &str, text: &str) -> Result<String> {
+    let body = serde_json::to_vec(&TranslateRequest { text, target: TARGET_LANGUAGE })?;
+    let (status, body) = outbound.post(url, "application/json", body)?;
+    anyhow::ensure!((200..300).contains(&status), "translation service returned {}", status);
+    let response: TranslateResponse =
+        serde_json::from_slice(&body).context("invalid translation response")?;
+    Ok(response.translation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::outbound::MockOutbound;
+
+    #[test]
+    fn test_translate() {
+        let outbound = MockOutbound(Box::new(|url, body| {
+            assert_eq!(url, "http://translate.local/v1");
+            let request: serde_json::Value = serde_json::from_slice(body).unwrap();
+            assert_eq!(request["text"], "hola");
+            assert_eq!(request["target"], "en");
+            Ok((200, br#"{"translation": "hello"}"#.to_vec()))
+        }));
+        assert_eq!(translate(&outbound, "http://translate.local/v1", "hola").unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_error_status_fails() {
+        let outbound = MockOutbound(Box::new(|_, _| Ok((503, Vec::new()))));
+        assert!(translate(&outbound, "http://translate.local/v1", "hola").is_err());
+    }
+}
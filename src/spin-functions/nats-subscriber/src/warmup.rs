@@ -0,0 +1,74 @@
+// Relaxed injection rules for the start of a conversation. System prompts
+// and setup tokens routinely mention phrases like "system prompt" without
+// being an injection, so the first `warmup_tokens` tokens of each
+// conversation are inspected without the `warmup_relaxed_patterns`. Every
+// other rule applies throughout. The count is deleted when the stream closes.
+
+use anyhow::Result;
+
+use crate::kv::{self, Store};
+use crate::policy::Policy;
+
+/// Injection patterns not applied during warmup unless
+/// `warmup_relaxed_patterns` is set, because setup text legitimately
+/// contains them.
+pub const DEFAULT_RELAXED_PATTERNS: &[&str] = &["system prompt", "new instructions"];
+
+fn key(conversation_id: &str) -> String {
+    format!("warmup/{}", conversation_id)
+}
+
+/// Count a token for the conversation and report whether it is still one
+/// of the first `warmup_tokens`.
+pub fn in_warmup(store: &dyn Store, conversation_id: &str, warmup_tokens: u64) -> Result<bool> {
+    let seen = kv::add_counter(store, &key(conversation_id), 1)?;
+    Ok(seen <= warmup_tokens)
+}
+
+/// Forget the conversation's count once its stream has closed.
+pub fn release(store: &dyn Store, conversation_id: &str) -> Result<()> {
+    store.delete(&key(conversation_id))
+}
+
+/// `policy` without the `relaxed_patterns` among its injection patterns.
+pub fn relaxed(policy: &Policy, relaxed_patterns: &[String]) -> Policy {
+    let injection_patterns = policy
+        .injection_patterns
+        .iter()
+        .filter(|pattern| !relaxed_patterns.iter().any(|relaxed| relaxed.eq_ignore_ascii_case(pattern)))
+        .cloned()
+        .collect();
+    Policy { injection_patterns, ..policy.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kv::MemoryStore;
+
+    #[test]
+    fn test_warmup_counted_per_conversation() {
+        let store = MemoryStore::default();
+        assert!(in_warmup(&store, "abc", 2).unwrap());
+        assert!(in_warmup(&store, "abc", 2).unwrap());
+        assert!(!in_warmup(&store, "abc", 2).unwrap());
+        assert!(in_warmup(&store, "xyz", 2).unwrap());
+    }
+
+    #[test]
+    fn test_released_count_starts_over() {
+        let store = MemoryStore::default();
+        assert!(in_warmup(&store, "abc", 1).unwrap());
+        assert!(!in_warmup(&store, "abc", 1).unwrap());
+        release(&store, "abc").unwrap();
+        assert_eq!(store.get(&key("abc")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_relaxed_keeps_other_patterns() {
+        let patterns: Vec<String> = DEFAULT_RELAXED_PATTERNS.iter().map(|p| p.to_string()).collect();
+        let relaxed = relaxed(&Policy::default(), &patterns);
+        assert!(!relaxed.injection_patterns.iter().any(|p| p == "system prompt"));
+        assert!(relaxed.injection_patterns.iter().any(|p| p == "ignore previous"));
+    }
+}
@@ -0,0 +1,5 @@
+// Helpers shared by the NATS publisher and subscriber Spin functions.
+
+pub mod problem;
+pub mod telemetry;
+pub mod variables;
@@ -0,0 +1,135 @@
+// RFC 7807 "Problem Details for HTTP APIs" error bodies.
+// Every error response from the Spin functions goes through here so clients
+// get one machine-readable shape regardless of which function failed.
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use spin_sdk::http::Response;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    /// URI identifying the problem type; `about:blank` when the status code
+    /// says everything there is to say.
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Extension members, serialized alongside the standard fields.
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// A problem of type `about:blank`, titled with the status reason phrase.
+    pub fn new(status: u16) -> Self {
+        Problem {
+            kind: "about:blank".to_string(),
+            title: reason_phrase(status).to_string(),
+            status,
+            detail: None,
+            extensions: Map::new(),
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_type(mut self, kind: impl Into<String>, title: impl Into<String>) -> Self {
+        self.kind = kind.into();
+        self.title = title.into();
+        self
+    }
+
+    pub fn with_extension(mut self, name: &str, value: impl Into<Value>) -> Self {
+        self.extensions.insert(name.to_string(), value.into());
+        self
+    }
+
+    pub fn into_response(self) -> Response {
+        let body = serde_json::to_string(&self).unwrap_or_default();
+        Response::builder()
+            .status(self.status)
+            .header("content-type", CONTENT_TYPE)
+            .body(body)
+            .build()
+    }
+}
+
+impl From<Problem> for Response {
+    fn from(problem: Problem) -> Self {
+        problem.into_response()
+    }
+}
+
+/// Shorthand for a plain problem response with a detail message.
+pub fn problem(status: u16, detail: impl Into<String>) -> Response {
+    Problem::new(status).with_detail(detail).into_response()
+}
+
+/// Response for an unexpected failure. The error chain is reported as the
+/// detail; callers must not pass errors that embed message content.
+pub fn internal_error(error: &anyhow::Error) -> Response {
+    problem(500, format!("{:#}", error))
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        415 => "Unsupported Media Type",
+        422 => "Unprocessable Entity",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(response.body()).unwrap()
+    }
+
+    #[test]
+    fn test_problem_fields() {
+        let response = problem(400, "missing subject");
+        assert_eq!(*response.status(), 400);
+        assert_eq!(response.header("content-type").unwrap().as_str(), Some(CONTENT_TYPE));
+        assert_eq!(
+            body(&response),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Bad Request",
+                "status": 400,
+                "detail": "missing subject",
+            })
+        );
+    }
+
+    #[test]
+    fn test_extensions_are_top_level_members() {
+        let response = Problem::new(429)
+            .with_extension("retry_after", 5)
+            .into_response();
+        let body = body(&response);
+        assert_eq!(body["title"], "Too Many Requests");
+        assert_eq!(body["retry_after"], 5);
+        assert!(body.get("detail").is_none());
+    }
+}
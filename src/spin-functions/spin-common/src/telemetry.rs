@@ -0,0 +1,286 @@
+// Optional OpenTelemetry tracing. Spans are collected for the duration of a
+// request and exported in one OTLP/HTTP (JSON) call when the request ends.
+// Without a collector endpoint the tracer records nothing, so instrumented
+// code pays no more than a branch.
+//
+// Spans carry metadata about the work (actions, codes, lengths) and must
+// never carry message content.
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use spin_sdk::http::{Method, Request, Response};
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A span attribute value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<usize> for AttributeValue {
+    fn from(value: usize) -> Self {
+        AttributeValue::Int(value as i64)
+    }
+}
+
+/// A finished span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpanData {
+    pub name: &'static str,
+    pub span_id: u64,
+    pub start_unix_nanos: u64,
+    pub end_unix_nanos: u64,
+    pub attributes: Vec<(&'static str, AttributeValue)>,
+}
+
+/// Destination for finished spans.
+pub trait Exporter {
+    fn export(&self, trace_id: u128, spans: &[SpanData]) -> Result<()>;
+}
+
+/// Collects the spans of one request under a single trace.
+pub struct Tracer {
+    exporter: Option<Box<dyn Exporter>>,
+    trace_id: u128,
+    spans: RefCell<Vec<SpanData>>,
+}
+
+impl Tracer {
+    /// A tracer that records nothing.
+    pub fn noop() -> Self {
+        Tracer { exporter: None, trace_id: 0, spans: RefCell::new(Vec::new()) }
+    }
+
+    pub fn new(exporter: Box<dyn Exporter>) -> Self {
+        let trace_id = (u128::from(random_u64()) << 64) | u128::from(random_u64());
+        Tracer { exporter: Some(exporter), trace_id, spans: RefCell::new(Vec::new()) }
+    }
+
+    /// An OTLP/HTTP tracer when `endpoint` is set, otherwise a no-op.
+    pub fn from_endpoint(endpoint: Option<&str>, service_name: &str) -> Self {
+        match endpoint {
+            Some(endpoint) => Tracer::new(Box::new(OtlpHttpExporter::new(endpoint, service_name))),
+            None => Tracer::noop(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.exporter.is_some()
+    }
+
+    /// Start a span, recorded when the returned guard is dropped.
+    pub fn span(&self, name: &'static str) -> Span<'_> {
+        Span {
+            tracer: self,
+            name,
+            start_unix_nanos: if self.is_enabled() { now_unix_nanos() } else { 0 },
+            attributes: Vec::new(),
+        }
+    }
+
+    /// Export every span recorded so far. Export failures are logged and
+    /// otherwise ignored; tracing must never fail a request.
+    pub fn flush(&self) {
+        let Some(exporter) = &self.exporter else {
+            return;
+        };
+        let spans = std::mem::take(&mut *self.spans.borrow_mut());
+        if spans.is_empty() {
+            return;
+        }
+        if let Err(e) = exporter.export(self.trace_id, &spans) {
+            eprintln!("warning: failed to export {} spans: {}", spans.len(), e);
+        }
+    }
+}
+
+/// An in-progress span.
+pub struct Span<'a> {
+    tracer: &'a Tracer,
+    name: &'static str,
+    start_unix_nanos: u64,
+    attributes: Vec<(&'static str, AttributeValue)>,
+}
+
+impl Span<'_> {
+    pub fn set_attribute(&mut self, key: &'static str, value: impl Into<AttributeValue>) {
+        if self.tracer.is_enabled() {
+            self.attributes.push((key, value.into()));
+        }
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        if !self.tracer.is_enabled() {
+            return;
+        }
+        self.tracer.spans.borrow_mut().push(SpanData {
+            name: self.name,
+            span_id: random_u64(),
+            start_unix_nanos: self.start_unix_nanos,
+            end_unix_nanos: now_unix_nanos(),
+            attributes: std::mem::take(&mut self.attributes),
+        });
+    }
+}
+
+/// Exports spans to an OTLP/HTTP collector, e.g. `http://collector:4318`.
+/// The collector must be listed in the component's `allowed_outbound_hosts`.
+pub struct OtlpHttpExporter {
+    url: String,
+    service_name: String,
+}
+
+impl OtlpHttpExporter {
+    pub fn new(endpoint: &str, service_name: &str) -> Self {
+        OtlpHttpExporter {
+            url: format!("{}/v1/traces", endpoint.trim_end_matches('/')),
+            service_name: service_name.to_string(),
+        }
+    }
+}
+
+impl Exporter for OtlpHttpExporter {
+    fn export(&self, trace_id: u128, spans: &[SpanData]) -> Result<()> {
+        let body = otlp_json(&self.service_name, trace_id, spans);
+        let request = Request::builder()
+            .method(Method::Post)
+            .uri(self.url.as_str())
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .build();
+        let response: Response = spin_sdk::http::run(spin_sdk::http::send(request))?;
+        anyhow::ensure!(
+            (200..300).contains(response.status()),
+            "collector returned {}",
+            response.status()
+        );
+        Ok(())
+    }
+}
+
+/// The OTLP/JSON `ExportTraceServiceRequest` for `spans`.
+pub fn otlp_json(service_name: &str, trace_id: u128, spans: &[SpanData]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            json!({
+                "traceId": format!("{:032x}", trace_id),
+                "spanId": format!("{:016x}", span.span_id),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": span.start_unix_nanos.to_string(),
+                "endTimeUnixNano": span.end_unix_nanos.to_string(),
+                "attributes": span.attributes.iter().map(|(key, value)| attribute(key, value)).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [attribute("service.name", &service_name.into())] },
+            "scopeSpans": [{ "scope": { "name": "spin-common" }, "spans": spans }],
+        }]
+    })
+}
+
+fn attribute(key: &str, value: &AttributeValue) -> Value {
+    let value = match value {
+        AttributeValue::String(s) => json!({ "stringValue": s }),
+        // OTLP/JSON encodes 64-bit integers as strings
+        AttributeValue::Int(i) => json!({ "intValue": i.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn now_unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Random ids without a `rand` dependency: `RandomState` is seeded from the
+/// platform's randomness source.
+fn random_u64() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
+
+/// Exporter that keeps exported spans in memory, for tests.
+#[derive(Default)]
+pub struct MemoryExporter(pub std::rc::Rc<RefCell<Vec<SpanData>>>);
+
+impl Exporter for MemoryExporter {
+    fn export(&self, _trace_id: u128, spans: &[SpanData]) -> Result<()> {
+        self.0.borrow_mut().extend_from_slice(spans);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span_attributes_exported_on_flush() {
+        let exporter = MemoryExporter::default();
+        let exported = exporter.0.clone();
+        let tracer = Tracer::new(Box::new(exporter));
+        {
+            let mut span = tracer.span("inspect_message");
+            span.set_attribute("inspection.action", "drop");
+            span.set_attribute("content.length", 12usize);
+        }
+        assert!(exported.borrow().is_empty());
+
+        tracer.flush();
+        let spans = exported.borrow();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "inspect_message");
+        assert_eq!(
+            spans[0].attributes,
+            vec![
+                ("inspection.action", AttributeValue::from("drop")),
+                ("content.length", AttributeValue::Int(12)),
+            ]
+        );
+        assert!(spans[0].end_unix_nanos >= spans[0].start_unix_nanos);
+    }
+
+    #[test]
+    fn test_noop_tracer_records_nothing() {
+        let tracer = Tracer::noop();
+        let mut span = tracer.span("inspect_message");
+        span.set_attribute("inspection.action", "allow");
+        drop(span);
+        assert!(tracer.spans.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_otlp_json_shape() {
+        let span = SpanData {
+            name: "bridge.publish",
+            span_id: 0xab,
+            start_unix_nanos: 1,
+            end_unix_nanos: 2,
+            attributes: vec![("content.length", AttributeValue::Int(5))],
+        };
+        let body = otlp_json("nats-publisher", 1, &[span]);
+        let resource = &body["resourceSpans"][0];
+        assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "nats-publisher");
+        let span = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["traceId"], "00000000000000000000000000000001");
+        assert_eq!(span["spanId"], "00000000000000ab");
+        assert_eq!(span["attributes"][0]["value"]["intValue"], "5");
+    }
+}
@@ -0,0 +1,58 @@
+// Spin application variables, abstracted so tests can supply a map. Each
+// function declares the variables it reads in its own spin.toml.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Source of configuration values.
+pub trait Variables {
+    fn get(&self, name: &str) -> Option<String>;
+}
+
+/// Reads variables from the Spin runtime. Undefined and empty variables are
+/// both treated as unset.
+pub struct SpinVariables;
+
+impl Variables for SpinVariables {
+    fn get(&self, name: &str) -> Option<String> {
+        spin_sdk::variables::get(name)
+            .ok()
+            .filter(|value| !value.is_empty())
+    }
+}
+
+impl Variables for HashMap<&str, &str> {
+    fn get(&self, name: &str) -> Option<String> {
+        HashMap::get(self, name).map(|value| value.to_string())
+    }
+}
+
+/// Parse a scalar variable, naming the variable in the error if it is malformed.
+pub fn parse<T>(vars: &dyn Variables, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    vars.get(name)
+        .map(|raw| {
+            raw.trim()
+                .parse()
+                .map_err(|e| anyhow::anyhow!("invalid `{}` variable: {}", name, e))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let vars = HashMap::from([("limit", " 42 "), ("bad", "x")]);
+        assert_eq!(parse::<u64>(&vars, "limit").unwrap(), Some(42));
+        assert_eq!(parse::<u64>(&vars, "missing").unwrap(), None);
+        let err = parse::<u64>(&vars, "bad").unwrap_err();
+        assert!(err.to_string().contains("invalid `bad` variable"));
+    }
+}